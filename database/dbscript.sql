@@ -2,25 +2,72 @@ create DATABASE rss;
 USE rss;
 
 CREATE TABLE user(
-	uid INT PRIMARY KEY
+	uid INT PRIMARY KEY AUTO_INCREMENT,
+	username VARCHAR(50) NOT NULL UNIQUE,
+	-- argon2id, in the encoded form that carries its own salt and parameters
+	password_hash VARCHAR(255) NOT NULL,
+	-- sha256 of the md5 of username:password, which Fever api clients log in with. Only set once the user turns Fever on
+	fever_key CHAR(64) UNIQUE
+);
+
+-- logins, the session cookie holds the token and only its sha256 is stored
+CREATE TABLE session (
+	token_hash CHAR(64) PRIMARY KEY,
+	uid INT NOT NULL,
+	created_at DATETIME NOT NULL,
+	expires_at DATETIME NOT NULL,
+	FOREIGN KEY (uid) REFERENCES user(uid) ON DELETE CASCADE
+);
+
+-- long-lived tokens for scripts and other clients, only their sha256 is stored
+CREATE TABLE api_token (
+	tid INT PRIMARY KEY AUTO_INCREMENT,
+	uid INT NOT NULL,
+	name VARCHAR(100) NOT NULL,
+	token_hash CHAR(64) NOT NULL UNIQUE,
+	-- json list of auth::Scope
+	scopes JSON NOT NULL,
+	created_at DATETIME NOT NULL,
+	last_used_at DATETIME,
+	FOREIGN KEY (uid) REFERENCES user(uid) ON DELETE CASCADE
 );
 
 CREATE TABLE channel(
 	cid INT PRIMARY KEY AUTO_INCREMENT,
 	uid INT,
 	name varchar(50),
+	-- when set, the sha256 of the token the channel's published feed needs to be read
+	feed_token CHAR(64),
+	-- set for smart channels, the SmartQuery that picks their posts
+	query JSON,
 	FOREIGN KEY (uid) REFERENCES user(uid)
 );
 
 CREATE TABLE publisher(
 	pid INT PRIMARY KEY AUTO_INCREMENT,
 	url VARCHAR(500) UNIQUE,
-	name VARCHAR(50)
+	name VARCHAR(50),
+	-- the website the feed belongs to
+	site_url VARCHAR(500),
+	-- cache validators from the last successful fetch
+	etag VARCHAR(255),
+	last_modified VARCHAR(64),
+	-- fetch health, failing feeds are backed off and eventually disabled
+	last_fetched DATETIME,
+	last_success DATETIME,
+	consecutive_failures INT NOT NULL DEFAULT 0,
+	last_status SMALLINT,
+	last_error VARCHAR(500),
+	disabled BOOLEAN NOT NULL DEFAULT FALSE
 );
 
 CREATE TABLE subscription (
 	cid INT,
 	pid INT,
+	-- the subscriber's overrides for how long the publisher's posts are kept,
+	-- null uses the global default and 0 keeps them forever
+	retention_days INT,
+	retention_posts INT,
 	PRIMARY KEY (cid, pid),
 	FOREIGN KEY (cid) REFERENCES channel(cid) ON DELETE CASCADE,
 	FOREIGN KEY (pid) REFERENCES publisher(pid)
@@ -28,15 +75,70 @@ CREATE TABLE subscription (
 
 CREATE TABLE post (
 	id INT PRIMARY KEY AUTO_INCREMENT,
-	url VARCHAR(500) UNIQUE,
+	url VARCHAR(500),
+	-- the feed's guid/id for the post, or its url without tracking params if it has none
+	guid VARCHAR(500) NOT NULL,
 	title VARCHAR(200),
 	content TEXT,
 	date_added DATETIME,
 	description VARCHAR(500),
 	image VARCHAR(500),
+	-- whoever the feed says wrote the post, several authors are separated by commas
+	author VARCHAR(200),
 	pid INT,
+	-- sha256 of the title, description and content, used to spot changed posts
+	content_hash CHAR(64),
+	updated_at DATETIME,
+	UNIQUE KEY (pid, guid),
+	FULLTEXT INDEX (title, description, content),
+	INDEX (url),
 	FOREIGN KEY (pid) REFERENCES publisher(pid)
 );
 
-INSERT into user (uid) VALUES (1);
-INSERT into channel(uid, name) values (1, "Arjun's main feed");
+-- previous versions of posts that the publisher changed after we stored them
+CREATE TABLE post_revision (
+	id INT PRIMARY KEY AUTO_INCREMENT,
+	post_id INT NOT NULL,
+	title VARCHAR(200),
+	content TEXT,
+	description VARCHAR(500),
+	revised_at DATETIME,
+	FOREIGN KEY (post_id) REFERENCES post(id) ON DELETE CASCADE
+);
+
+-- what each user has done with a post
+CREATE TABLE post_state (
+	uid INT,
+	id INT,
+	read_at DATETIME,
+	starred_at DATETIME,
+	-- a copy of the starred post's article, in case the original goes away
+	snapshot MEDIUMTEXT,
+	-- set when one of the user's filter rules hid the post
+	muted_at DATETIME,
+	PRIMARY KEY (uid, id),
+	FOREIGN KEY (uid) REFERENCES user(uid),
+	FOREIGN KEY (id) REFERENCES post(id) ON DELETE CASCADE
+);
+
+-- rules that mute a user's posts as they're ingested, see filter::FilterRule
+CREATE TABLE filter_rule (
+	fid INT PRIMARY KEY AUTO_INCREMENT,
+	uid INT NOT NULL,
+	-- the rule only applies to posts from this channel's subscriptions, or all of them if null
+	cid INT,
+	-- the rule only applies to posts from this publisher, or all of them if null
+	pid INT,
+	-- keyword, regex or publisher
+	kind VARCHAR(16) NOT NULL,
+	pattern VARCHAR(255) NOT NULL DEFAULT '',
+	-- title or full
+	field VARCHAR(16) NOT NULL,
+	-- hide or read
+	action VARCHAR(16) NOT NULL,
+	FOREIGN KEY (uid) REFERENCES user(uid),
+	FOREIGN KEY (cid) REFERENCES channel(cid) ON DELETE CASCADE,
+	FOREIGN KEY (pid) REFERENCES publisher(pid) ON DELETE CASCADE
+);
+
+-- accounts are made with POST /register, which also gives them their first channel
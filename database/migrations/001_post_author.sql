@@ -0,0 +1,5 @@
+-- adds the post authors parsed from feeds to an existing database.
+-- dbscript.sql already has this for new databases.
+USE rss;
+
+ALTER TABLE post ADD COLUMN author VARCHAR(200) AFTER image;
@@ -0,0 +1,15 @@
+-- upgrades a post table from before posts were told apart by their feed's guid,
+-- when url was unique and there was no guid column. dbscript.sql already has this for new databases.
+USE rss;
+
+ALTER TABLE post ADD COLUMN guid VARCHAR(500) AFTER url;
+
+-- the feed's guid isn't known until the post is fetched again, so stored posts are marked
+-- with their url for now. insert_posts finds them by it and gives them their real guid.
+UPDATE post SET guid=LEFT(CONCAT('legacy:', COALESCE(url, id)), 500);
+
+ALTER TABLE post
+	MODIFY guid VARCHAR(500) NOT NULL,
+	DROP INDEX url,
+	ADD UNIQUE KEY (pid, guid),
+	ADD INDEX (url);
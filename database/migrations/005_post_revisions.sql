@@ -0,0 +1,19 @@
+-- adds what's needed to spot posts the publisher changed and keep their old versions.
+-- dbscript.sql already has this for new databases.
+USE rss;
+
+ALTER TABLE post
+	-- sha256 of the title, description and content, used to spot changed posts
+	ADD COLUMN content_hash CHAR(64) AFTER pid,
+	ADD COLUMN updated_at DATETIME AFTER content_hash;
+
+-- previous versions of posts that the publisher changed after we stored them
+CREATE TABLE post_revision (
+	id INT PRIMARY KEY AUTO_INCREMENT,
+	post_id INT NOT NULL,
+	title VARCHAR(200),
+	content TEXT,
+	description VARCHAR(500),
+	revised_at DATETIME,
+	FOREIGN KEY (post_id) REFERENCES post(id) ON DELETE CASCADE
+);
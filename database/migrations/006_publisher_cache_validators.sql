@@ -0,0 +1,8 @@
+-- adds the cache validators conditional fetches send back to the publisher.
+-- dbscript.sql already has this for new databases.
+USE rss;
+
+ALTER TABLE publisher
+	-- cache validators from the last successful fetch
+	ADD COLUMN etag VARCHAR(255),
+	ADD COLUMN last_modified VARCHAR(64);
@@ -0,0 +1,11 @@
+-- adds how fetching each publisher has been going, so failing feeds can be backed off and disabled.
+-- dbscript.sql already has this for new databases.
+USE rss;
+
+ALTER TABLE publisher
+	ADD COLUMN last_fetched DATETIME,
+	ADD COLUMN last_success DATETIME,
+	ADD COLUMN consecutive_failures INT NOT NULL DEFAULT 0,
+	ADD COLUMN last_status SMALLINT,
+	ADD COLUMN last_error VARCHAR(500),
+	ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,5 @@
+-- adds the website each feed belongs to, written to OPML exports.
+-- dbscript.sql already has this for new databases.
+USE rss;
+
+ALTER TABLE publisher ADD COLUMN site_url VARCHAR(500) AFTER name;
@@ -0,0 +1,6 @@
+-- adds the token private published feeds need to be read.
+-- dbscript.sql already has this for new databases.
+USE rss;
+
+-- when set, the sha256 of the token the channel's published feed needs to be read
+ALTER TABLE channel ADD COLUMN feed_token CHAR(64) AFTER name;
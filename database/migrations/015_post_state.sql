@@ -0,0 +1,12 @@
+-- adds per-user read state. dbscript.sql already has this for new databases.
+USE rss;
+
+-- what each user has done with a post
+CREATE TABLE post_state (
+	uid INT,
+	id INT,
+	read_at DATETIME,
+	PRIMARY KEY (uid, id),
+	FOREIGN KEY (uid) REFERENCES user(uid),
+	FOREIGN KEY (id) REFERENCES post(id) ON DELETE CASCADE
+);
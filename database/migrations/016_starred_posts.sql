@@ -0,0 +1,7 @@
+-- adds starring posts, run after 015_post_state.sql. dbscript.sql already has this for new databases.
+USE rss;
+
+ALTER TABLE post_state
+	ADD COLUMN starred_at DATETIME AFTER read_at,
+	-- a copy of the starred post's article, in case the original goes away
+	ADD COLUMN snapshot MEDIUMTEXT AFTER starred_at;
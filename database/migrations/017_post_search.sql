@@ -0,0 +1,5 @@
+-- adds the full-text index search needs. dbscript.sql already has this for new databases.
+-- Building it can take a while on a big post table.
+USE rss;
+
+ALTER TABLE post ADD FULLTEXT INDEX (title, description, content);
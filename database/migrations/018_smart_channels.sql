@@ -0,0 +1,5 @@
+-- adds smart channels. dbscript.sql already has this for new databases.
+USE rss;
+
+-- set for smart channels, the SmartQuery that picks their posts
+ALTER TABLE channel ADD COLUMN query JSON AFTER feed_token;
@@ -0,0 +1,25 @@
+-- adds mute filters, run after 016_starred_posts.sql. dbscript.sql already has this for new databases.
+USE rss;
+
+-- set when one of the user's filter rules hid the post
+ALTER TABLE post_state ADD COLUMN muted_at DATETIME AFTER snapshot;
+
+-- rules that mute a user's posts as they're ingested, see filter::FilterRule
+CREATE TABLE filter_rule (
+	fid INT PRIMARY KEY AUTO_INCREMENT,
+	uid INT NOT NULL,
+	-- the rule only applies to posts from this channel's subscriptions, or all of them if null
+	cid INT,
+	-- the rule only applies to posts from this publisher, or all of them if null
+	pid INT,
+	-- keyword, regex or publisher
+	kind VARCHAR(16) NOT NULL,
+	pattern VARCHAR(255) NOT NULL DEFAULT '',
+	-- title or full
+	field VARCHAR(16) NOT NULL,
+	-- hide or read
+	action VARCHAR(16) NOT NULL,
+	FOREIGN KEY (uid) REFERENCES user(uid),
+	FOREIGN KEY (cid) REFERENCES channel(cid) ON DELETE CASCADE,
+	FOREIGN KEY (pid) REFERENCES publisher(pid) ON DELETE CASCADE
+);
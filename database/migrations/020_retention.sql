@@ -0,0 +1,8 @@
+-- adds each subscriber's retention overrides. dbscript.sql already has this for new databases.
+USE rss;
+
+ALTER TABLE subscription
+	-- the subscriber's overrides for how long the publisher's posts are kept,
+	-- null uses the global default and 0 keeps them forever
+	ADD COLUMN retention_days INT AFTER pid,
+	ADD COLUMN retention_posts INT AFTER retention_days;
@@ -0,0 +1,31 @@
+-- adds logins. dbscript.sql already has this for new databases.
+-- Existing users get the username user<uid> and no password, so they can't log in.
+-- Register a new account, then hand it the old channels with
+-- UPDATE channel SET uid=<new uid> WHERE uid=<old uid>;
+USE rss;
+
+-- uid is referenced by channel and post_state, which MySQL won't allow to be modified otherwise
+SET FOREIGN_KEY_CHECKS=0;
+ALTER TABLE user MODIFY uid INT AUTO_INCREMENT;
+SET FOREIGN_KEY_CHECKS=1;
+
+ALTER TABLE user
+	ADD COLUMN username VARCHAR(50) UNIQUE,
+	-- argon2id, in the encoded form that carries its own salt and parameters
+	ADD COLUMN password_hash VARCHAR(255);
+
+-- an empty hash never verifies
+UPDATE user SET username=CONCAT('user', uid), password_hash='';
+
+ALTER TABLE user
+	MODIFY username VARCHAR(50) NOT NULL,
+	MODIFY password_hash VARCHAR(255) NOT NULL;
+
+-- logins, the session cookie holds the token and only its sha256 is stored
+CREATE TABLE session (
+	token_hash CHAR(64) PRIMARY KEY,
+	uid INT NOT NULL,
+	created_at DATETIME NOT NULL,
+	expires_at DATETIME NOT NULL,
+	FOREIGN KEY (uid) REFERENCES user(uid) ON DELETE CASCADE
+);
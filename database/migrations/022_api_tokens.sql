@@ -0,0 +1,15 @@
+-- adds api tokens, run after 021_accounts.sql. dbscript.sql already has this for new databases.
+USE rss;
+
+-- long-lived tokens for scripts and other clients, only their sha256 is stored
+CREATE TABLE api_token (
+	tid INT PRIMARY KEY AUTO_INCREMENT,
+	uid INT NOT NULL,
+	name VARCHAR(100) NOT NULL,
+	token_hash CHAR(64) NOT NULL UNIQUE,
+	-- json list of auth::Scope
+	scopes JSON NOT NULL,
+	created_at DATETIME NOT NULL,
+	last_used_at DATETIME,
+	FOREIGN KEY (uid) REFERENCES user(uid) ON DELETE CASCADE
+);
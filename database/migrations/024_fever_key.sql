@@ -0,0 +1,5 @@
+-- adds the Fever api key, run after 021_accounts.sql. dbscript.sql already has this for new databases.
+USE rss;
+
+-- sha256 of the md5 of username:password, which Fever api clients log in with. Only set once the user turns Fever on
+ALTER TABLE user ADD COLUMN fever_key CHAR(64) UNIQUE AFTER password_hash;
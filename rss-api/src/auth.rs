@@ -0,0 +1,334 @@
+use crate::database::DatabaseConnection;
+use crate::logger::DetailedError;
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap, Method, StatusCode},
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use tracing::{event, Level};
+
+// the cookie holding the session token
+pub const SESSION_COOKIE: &str = "session";
+// how long a login lasts
+pub const SESSION_DAYS: i64 = 30;
+// starts every api token, so they're easy to spot in scripts and logs
+pub const API_TOKEN_PREFIX: &str = "rss_";
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_USERNAME_LENGTH: usize = 50;
+// checked against when the username doesn't exist, so a login takes as long either way
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$hAS8+3D9u6b0DSk9uG6HFw$FwI7i2pRLqaS0GZUCUqThVIfu4aJ2i68UK+GR51HXf0";
+
+/// AuthUser is the logged in user making the request.
+/// Handlers that take it answer 401 to anyone without a valid session or api token,
+/// and 403 to api tokens without the scope the request needs, see required_scope.
+pub struct AuthUser {
+    pub uid: u64,
+}
+
+/// Scope is something an api token is allowed to do. A session can do everything.
+/// Each scope also allows everything the ones before it do, so Manage covers Write and Read.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    // read posts, channels and subscriptions
+    Read,
+    // mark posts as read and star them
+    Write,
+    // change subscriptions, channels, filters and other settings
+    Manage,
+}
+
+/// ApiToken describes a token without the token itself, which is only shown when it's made
+#[derive(Serialize)]
+pub struct ApiToken {
+    pub tid: u64,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    DatabaseConnection: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let unauthorized = (StatusCode::UNAUTHORIZED, "Not logged in".to_string());
+        let dbconn = DatabaseConnection::from_ref(state);
+
+        let res = match bearer_token(&parts.headers) {
+            Some(token) => {
+                let required = match required_scope(&parts.method, parts.uri.path()) {
+                    Some(val) => val,
+                    None => {
+                        return Err((
+                            StatusCode::FORBIDDEN,
+                            "API tokens can't be used here".to_string(),
+                        ))
+                    }
+                };
+                match dbconn.use_api_token(&hash_token(token)).await {
+                    Ok(Some((_, scopes))) if !allows(&scopes, required) => {
+                        return Err((
+                            StatusCode::FORBIDDEN,
+                            format!("The token needs the {required:?} scope"),
+                        ))
+                    }
+                    res => res.map(|x| x.map(|(uid, _)| uid)),
+                }
+            }
+            None => {
+                let jar = CookieJar::from_headers(&parts.headers);
+                // reader api clients send their session in a header instead of a cookie
+                let token = match google_login_token(&parts.headers) {
+                    Some(val) => val,
+                    None => match jar.get(SESSION_COOKIE) {
+                        Some(cookie) => cookie.value(),
+                        None => return Err(unauthorized),
+                    },
+                };
+                dbconn.get_session_user(&hash_token(token)).await
+            }
+        };
+        match res {
+            Ok(Some(uid)) => Ok(AuthUser { uid }),
+            Ok(None) => Err(unauthorized),
+            Err(e) => {
+                event!(
+                    Level::ERROR,
+                    backtrace = ?e,
+                    description = e.desc,
+                );
+                Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "We had some issues with the request...".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+// bearer_token is the api token in the Authorization header, if there is one
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|x| x.trim())
+}
+
+// google_login_token is the session token a Google Reader API client got from ClientLogin
+fn google_login_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("GoogleLogin auth=")
+        .map(|x| x.trim())
+}
+
+/// required_scope is the scope an api token needs for a request.
+/// Nothing means tokens can't be used at all, like for managing tokens.
+pub fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    if path == "/token" || path.starts_with("/token/") {
+        return None;
+    }
+    // reading an article only marks it as read as it goes
+    if method == Method::GET || path == "/read" {
+        return Some(Scope::Read);
+    }
+    match path {
+        "/read/mark" | "/star" => Some(Scope::Write),
+        _ => Some(Scope::Manage),
+    }
+}
+
+/// allows is whether a token with the given scopes can make a request needing required
+pub fn allows(scopes: &[Scope], required: Scope) -> bool {
+    scopes.iter().any(|x| *x >= required)
+}
+
+/// check_credentials makes sure a new account's username and password are usable,
+/// returning what's wrong with them if not
+pub fn check_credentials(username: &str, password: &str) -> Result<(), String> {
+    if username.is_empty() || username.chars().count() > MAX_USERNAME_LENGTH {
+        return Err(format!(
+            "The username has to be between 1 and {MAX_USERNAME_LENGTH} characters"
+        ));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        return Err(
+            "The username can only have letters, numbers, dots, dashes and underscores".to_string(),
+        );
+    }
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "The password has to be at least {MIN_PASSWORD_LENGTH} characters"
+        ));
+    }
+    Ok(())
+}
+
+/// hash_password hashes the password with argon2id and a random salt, for the user table
+pub fn hash_password(password: &str) -> Result<String, DetailedError> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    match argon2::hash_encoded(password.as_bytes(), &salt, &argon2::Config::default()) {
+        Ok(val) => Ok(val),
+        Err(e) => Err(DetailedError::new(Box::new(e))),
+    }
+}
+
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+}
+
+/// check_login verifies the password for what get_login found, giving back the uid if it's right.
+/// A password is still hashed when there's no such user so the timing doesn't give usernames away.
+pub fn check_login(login: Option<(u64, String)>, password: &str) -> Option<u64> {
+    match login {
+        Some((uid, hash)) => Some(uid).filter(|_| verify_password(&hash, password)),
+        None => {
+            verify_password(DUMMY_HASH, password);
+            None
+        }
+    }
+}
+
+/// new_api_token makes a random api token
+pub fn new_api_token() -> String {
+    format!("{API_TOKEN_PREFIX}{}", new_token())
+}
+
+/// new_token makes a random token for a session
+pub fn new_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect()
+}
+
+/// hash_token is what tokens are stored as, so a leaked database doesn't leak working tokens
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// session_cookie holds the token of a new session
+pub fn session_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((SESSION_COOKIE, token))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        // only the docker deployment is served over https
+        .secure(env::var("IS_DOCKER_COMPOSED").is_ok())
+        .max_age(time::Duration::days(SESSION_DAYS))
+        .build()
+}
+
+/// removal_cookie clears the session cookie when logging out
+pub fn removal_cookie() -> Cookie<'static> {
+    Cookie::build((SESSION_COOKIE, "")).path("/").build()
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use super::*;
+
+    #[test]
+    fn test_passwords() {
+        let hash = match hash_password("correct horse") {
+            Ok(val) => val,
+            Err(error) => panic!("{error}"),
+        };
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password(&hash, "correct horse"));
+        assert!(!verify_password(&hash, "battery staple"));
+        assert!(!verify_password("not a hash", "correct horse"));
+        assert_eq!(
+            check_login(Some((1, hash.clone())), "correct horse"),
+            Some(1)
+        );
+        assert_eq!(check_login(Some((1, hash.clone())), "battery staple"), None);
+        assert_eq!(check_login(None, "not a real password"), None);
+        // salted, so the same password hashes differently
+        assert_ne!(Some(hash), hash_password("correct horse").ok());
+    }
+
+    #[test]
+    fn test_check_credentials() {
+        assert!(check_credentials("arjun", "long enough").is_ok());
+        assert!(check_credentials("", "long enough").is_err());
+        assert!(check_credentials("ar jun", "long enough").is_err());
+        assert!(check_credentials(&"a".repeat(51), "long enough").is_err());
+        assert!(check_credentials("arjun", "short").is_err());
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(required_scope(&Method::GET, "/feed"), Some(Scope::Read));
+        assert_eq!(required_scope(&Method::POST, "/read"), Some(Scope::Read));
+        assert_eq!(
+            required_scope(&Method::POST, "/read/mark"),
+            Some(Scope::Write)
+        );
+        assert_eq!(required_scope(&Method::DELETE, "/star"), Some(Scope::Write));
+        assert_eq!(required_scope(&Method::POST, "/sub"), Some(Scope::Manage));
+        assert_eq!(
+            required_scope(&Method::DELETE, "/channel"),
+            Some(Scope::Manage)
+        );
+        // tokens can't make more tokens
+        assert_eq!(required_scope(&Method::GET, "/token"), None);
+        assert_eq!(required_scope(&Method::POST, "/token"), None);
+    }
+
+    #[test]
+    fn test_allows() {
+        assert!(allows(&[Scope::Manage], Scope::Read));
+        assert!(allows(&[Scope::Manage], Scope::Write));
+        assert!(allows(&[Scope::Read, Scope::Write], Scope::Write));
+        assert!(!allows(&[Scope::Read], Scope::Write));
+        assert!(!allows(&[Scope::Write], Scope::Manage));
+        assert!(!allows(&[], Scope::Read));
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+        headers.insert(AUTHORIZATION, "Basic abc".parse().unwrap());
+        assert_eq!(bearer_token(&headers), None);
+        headers.insert(AUTHORIZATION, "Bearer rss_abc".parse().unwrap());
+        assert_eq!(bearer_token(&headers), Some("rss_abc"));
+        assert_eq!(google_login_token(&headers), None);
+        headers.insert(AUTHORIZATION, "GoogleLogin auth=abc".parse().unwrap());
+        assert_eq!(google_login_token(&headers), Some("abc"));
+        assert_eq!(bearer_token(&headers), None);
+    }
+
+    #[test]
+    fn test_tokens() {
+        let token = new_token();
+        assert_eq!(token.len(), 40);
+        assert_ne!(token, new_token());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_eq!(hash_token(&token).len(), 64);
+        assert!(new_api_token().starts_with(API_TOKEN_PREFIX));
+    }
+}
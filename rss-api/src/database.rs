@@ -1,9 +1,92 @@
+use crate::auth::{ApiToken, Scope};
+use crate::filter::{Filter, FilterAction, FilterRule};
+use crate::greader::StreamFilters;
 use crate::logger::DetailedError;
-use crate::{rss_parser::validate_feed, Channel, Post, Subscription};
-use chrono::NaiveDateTime;
-use mysql::{params, prelude::Queryable, Pool};
+use crate::opml::{parse_opml, write_opml, OpmlFeed, OpmlFolder};
+use crate::rss_parser::{discover_feeds, normalize_url, validate_feed, FeedCandidate, FetchResult};
+use crate::search::{SearchFilters, SearchQuery};
+use crate::{
+    Channel, FeedStatus, Post, PostVersion, RetentionPolicy, SmartQuery, Subscription,
+    MAX_CONSECUTIVE_FAILURES,
+};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use mysql::{params, prelude::Queryable, Pool, Row, TxOpts};
+use serde::{de::DeserializeOwned, Serialize};
 use std::env;
 
+// a full row from the post table, along with the publisher's name
+type PostRow = (
+    u64,
+    String,
+    String,
+    Option<String>,
+    NaiveDateTime,
+    String,
+    Option<String>,
+    u64,
+    String,
+    Option<NaiveDateTime>,
+    Option<String>,
+);
+
+// a post in a user's list, without its content but with whether they've read and starred it
+type PostListRow = (
+    u64,
+    String,
+    String,
+    NaiveDateTime,
+    String,
+    Option<String>,
+    u64,
+    String,
+    Option<NaiveDateTime>,
+    bool,
+    bool,
+);
+
+// a starred post, with its snapshot as the content and whether it's been read
+type StarredRow = (
+    u64,
+    String,
+    String,
+    Option<String>,
+    NaiveDateTime,
+    String,
+    Option<String>,
+    u64,
+    String,
+    Option<NaiveDateTime>,
+    bool,
+);
+
+/// SubscribeOutcome is what came of subscribing to a url
+pub enum SubscribeOutcome {
+    Subscribed,
+    // the url had several feeds, so the user has to pick one
+    Candidates(Vec<FeedCandidate>),
+}
+
+/// ImportResult is how importing one feed from an OPML document went
+#[derive(Serialize)]
+pub struct ImportResult {
+    pub url: String,
+    pub title: Option<String>,
+    // the channel the feed was imported into
+    pub channel: String,
+    pub status: ImportStatus,
+    // why the feed couldn't be subscribed to, if it failed
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Subscribed,
+    // the channel was already subscribed to the feed
+    Duplicate,
+    Failed,
+}
+
 pub struct DatabaseConnection {
     pool: Pool,
 }
@@ -18,35 +101,77 @@ impl DatabaseConnection {
         DatabaseConnection { pool }
     }
 
-    pub async fn subscribe(&self, cid: u64, url: String) -> Result<(), DetailedError> {
+    /// subscribe adds the feed at url to the channel. The url can also be a web page,
+    /// in which case its feed is found for it. If the page has more than one feed,
+    /// nothing is subscribed to and the feeds are returned for the user to choose from.
+    pub async fn subscribe(
+        &self,
+        cid: u64,
+        url: String,
+    ) -> Result<SubscribeOutcome, DetailedError> {
         let mut conn = self.pool.get_conn().unwrap();
         let query = match conn.prep("Select pid from publisher where url=:url") {
             Ok(val) => val,
             Err(e) => return Err(DetailedError::new(Box::new(e))),
         };
 
-        let res: Result<Option<u64>, mysql::Error> = conn.exec_first(query, params! {"url"=>&url});
+        let res: Result<Option<u64>, mysql::Error> = conn.exec_first(&query, params! {"url"=>&url});
 
         let id = match res {
             Ok(Some(id)) => id,
             Ok(None) => {
-                let name = match validate_feed(&url).await {
+                let mut found = match discover_feeds(&url).await {
                     Ok(val) => val,
                     Err(e) => {
-                        return Err(DetailedError::new_descriptive(
-                            e,
-                            "Could not validate feed: ",
-                        ))
+                        return Err(DetailedError::new_descriptive(e, "Could not find a feed: "))
                     }
                 };
-                let query = match conn.prep("INSERT INTO publisher(url, name) VALUES (:url, :name)")
-                {
-                    Ok(val) => val,
-                    Err(e) => return Err(DetailedError::new(Box::new(e))),
+                let found = match found.len() {
+                    0 => {
+                        return Err(DetailedError::new_with_message(
+                            "Could not find a feed at this url",
+                        ))
+                    }
+                    1 => found.remove(0),
+                    _ => return Ok(SubscribeOutcome::Candidates(found)),
                 };
-                let res = conn.exec_drop(query, params! {"url" => &url, "name" => name});
+                let url = found.url;
+
+                // the page's feed may be one we already have
+                let res: Result<Option<u64>, mysql::Error> =
+                    conn.exec_first(&query, params! {"url"=>&url});
                 match res {
-                    Ok(()) => conn.last_insert_id(),
+                    Ok(Some(id)) => id,
+                    Ok(None) => {
+                        // feeds found by downloading them don't need downloading again
+                        let info = match found.info {
+                            Some(info) => Ok(info),
+                            None => validate_feed(&url).await,
+                        };
+                        let info = match info {
+                            Ok(val) => val,
+                            Err(e) => {
+                                return Err(DetailedError::new_descriptive(
+                                    e,
+                                    "Could not validate feed: ",
+                                ))
+                            }
+                        };
+                        let query = match conn
+                            .prep("INSERT INTO publisher(url, name, site_url) VALUES (:url, :name, :site_url)")
+                        {
+                            Ok(val) => val,
+                            Err(e) => return Err(DetailedError::new(Box::new(e))),
+                        };
+                        let res = conn.exec_drop(
+                            query,
+                            params! {"url" => &url, "name" => info.title, "site_url" => info.site_url},
+                        );
+                        match res {
+                            Ok(()) => conn.last_insert_id(),
+                            Err(e) => return Err(DetailedError::new(Box::new(e))),
+                        }
+                    }
                     Err(e) => return Err(DetailedError::new(Box::new(e))),
                 }
             }
@@ -63,8 +188,103 @@ impl DatabaseConnection {
         if let Err(e) = res {
             Err(DetailedError::new(Box::new(e)))
         } else {
-            Ok(())
+            Ok(SubscribeOutcome::Subscribed)
+        }
+    }
+
+    /// import_opml subscribes the user to every feed in an OPML document.
+    /// Top level folders become channels, reusing the user's channel of the same name if there is one.
+    /// A feed that can't be subscribed to doesn't stop the rest, it's reported as failed instead.
+    pub async fn import_opml(
+        &self,
+        uid: u64,
+        opml: &str,
+    ) -> Result<Vec<ImportResult>, DetailedError> {
+        let folders = match parse_opml(opml) {
+            Ok(val) => val,
+            Err(e) => return Err(DetailedError::new_descriptive(e, "Could not read OPML: ")),
+        };
+        let mut channels = self.get_channels_for_user(uid).await?;
+        let mut report = vec![];
+
+        for folder in folders {
+            let cid = match channels.iter().find(|x| x.name == folder.title) {
+                Some(channel) => channel.cid,
+                None => {
+                    let cid = self
+                        .insert_channel_for_user(uid, folder.title.clone(), None)
+                        .await?;
+                    channels.push(Channel {
+                        cid,
+                        name: folder.title.clone(),
+                        private: false,
+                        query: None,
+                    });
+                    cid
+                }
+            };
+
+            for feed in folder.feeds {
+                let (status, error) = if self.is_subscribed(cid, &feed.xml_url).await? {
+                    (ImportStatus::Duplicate, None)
+                } else {
+                    match self.subscribe(cid, feed.xml_url.clone()).await {
+                        Ok(SubscribeOutcome::Subscribed) => (ImportStatus::Subscribed, None),
+                        Ok(SubscribeOutcome::Candidates(_)) => (
+                            ImportStatus::Failed,
+                            Some("The url has more than one feed".to_string()),
+                        ),
+                        Err(e) => (
+                            ImportStatus::Failed,
+                            Some(e.friendly_desc.unwrap_or("".to_string()) + &e.desc),
+                        ),
+                    }
+                };
+                report.push(ImportResult {
+                    url: feed.xml_url,
+                    title: feed.title,
+                    channel: folder.title.clone(),
+                    status,
+                    error,
+                });
+            }
         }
+        Ok(report)
+    }
+
+    /// export_opml builds an OPML document of the user's subscriptions, with a folder for each channel
+    pub async fn export_opml(&self, uid: u64) -> Result<String, DetailedError> {
+        let channels = self.get_channels_for_user(uid).await?;
+        let mut folders = vec![];
+        for channel in channels {
+            let feeds = self
+                .get_subbed(channel.cid)
+                .await?
+                .into_iter()
+                .map(|x| OpmlFeed {
+                    title: Some(x.name),
+                    xml_url: x.url,
+                    html_url: x.site_url,
+                })
+                .collect();
+            folders.push(OpmlFolder {
+                title: channel.name,
+                feeds,
+            });
+        }
+        Ok(write_opml("RSS Reader subscriptions", &folders))
+    }
+
+    // is_subscribed checks if the channel already has the publisher at url
+    async fn is_subscribed(&self, cid: u64, url: &str) -> Result<bool, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let res: Option<u64> = conn.exec_first(
+            r"SELECT s.pid FROM subscription s INNER JOIN publisher p ON s.pid=p.pid
+                WHERE s.cid=:cid AND p.url=:url",
+            params! {"cid" => cid, "url" => url},
+        )?;
+        Ok(res.is_some())
     }
 
     pub async fn unsubscribe(&self, pid: u64, cid: u64) -> Result<(), DetailedError> {
@@ -83,15 +303,24 @@ impl DatabaseConnection {
     pub async fn get_subbed(&self, cid: u64) -> Result<Vec<Subscription>, DetailedError> {
         let mut conn = self.pool.get_conn().unwrap();
 
-        let query = conn.prep("SELECT url, subscription.pid, name from subscription INNER JOIN publisher on subscription.pid=publisher.pid where cid=:cid")?;
+        let query = conn.prep(format!("SELECT url, subscription.pid, name, site_url, etag, last_modified, subscription.retention_days, subscription.retention_posts, {STATUS_COLUMNS} from subscription INNER JOIN publisher on subscription.pid=publisher.pid where cid=:cid"))?;
 
         Ok(
-            conn.exec_map(query, params! {"cid" => cid}, |(url, pid, name)| {
+            conn.exec_map(query, params! {"cid" => cid}, |mut row: Row| {
+                let retention = RetentionPolicy {
+                    days: row.take("retention_days").unwrap(),
+                    posts: row.take("retention_posts").unwrap(),
+                };
                 Subscription {
-                    cid: cid,
-                    pid: pid,
-                    url: url,
-                    name: name,
+                    cid,
+                    pid: row.take("pid"),
+                    url: row.take("url").unwrap(),
+                    name: row.take("name").unwrap(),
+                    site_url: row.take("site_url").unwrap(),
+                    etag: row.take("etag").unwrap(),
+                    last_modified: row.take("last_modified").unwrap(),
+                    status: Some(feed_status(&mut row)),
+                    retention: Some(retention).filter(|x| x.days.is_some() || x.posts.is_some()),
                 }
             })?,
         )
@@ -100,89 +329,196 @@ impl DatabaseConnection {
     pub async fn get_subbed_for_user(&self, uid: u64) -> Result<Vec<Subscription>, DetailedError> {
         let mut conn = self.pool.get_conn().unwrap();
 
-        let query = conn.prep(
-            " SELECT url, subscription.pid, publisher.name from subscription \
+        let query = conn.prep(format!(
+            " SELECT url, subscription.pid, publisher.name, {STATUS_COLUMNS} from subscription \
             INNER JOIN publisher on publisher.pid=subscription.pid \
             INNER JOIN channel on subscription.cid=channel.cid \
             WHERE uid=:uid
             ",
-        )?;
+        ))?;
 
         Ok(
-            conn.exec_map(query, params! {"uid" => uid}, |(url, pid, name)| {
+            conn.exec_map(query, params! {"uid" => uid}, |mut row: Row| {
                 Subscription {
                     // don't need cid for this response.
                     cid: 0,
-                    pid: pid,
-                    url: url,
-                    name: name,
+                    pid: row.take("pid"),
+                    url: row.take("url").unwrap(),
+                    name: row.take("name").unwrap(),
+                    status: Some(feed_status(&mut row)),
+                    ..Default::default()
                 }
             })?,
         )
     }
 
-    pub async fn insert_posts(&self, posts: &Vec<crate::Post>) -> Result<(), DetailedError> {
-        let mut conn = self.pool.get_conn().unwrap();
+    /// insert_posts stores new posts and updates the ones the publisher has changed since we last saw them.
+    /// The previous version of a changed post is kept in the post_revision table.
+    /// It returns the new posts along with the ids they were stored under.
+    pub async fn insert_posts<'a>(
+        &self,
+        posts: &'a Vec<crate::Post>,
+    ) -> Result<Vec<(u64, &'a Post)>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
 
-        conn.exec_batch(
-            r"INSERT IGNORE into post (url, title, content, date_added, description, image, pid) 
-                VALUES (:url, :title, :content, :date_added, :description, :image, :pid)",
-            posts.iter().map(|p| {
+        let existing =
+            conn.prep("SELECT id, content_hash FROM post WHERE pid=:pid AND guid=:guid")?;
+        let legacy = conn.prep(
+            "SELECT id, content_hash FROM post WHERE pid=:pid AND guid IN (LEFT(CONCAT(:prefix, :url), 500), LEFT(CONCAT(:prefix, :normalized), 500))",
+        )?;
+        let mut new_posts = Vec::new();
+        for p in posts {
+            let hash = p.content_hash();
+            let mut res: Option<(u64, Option<String>)> =
+                conn.exec_first(&existing, params! {"pid" => p.pid, "guid" => p.identity()})?;
+            if res.is_none() {
+                // stored before posts had guids, it takes this one's now that we know it
+                res = conn.exec_first(
+                    &legacy,
+                    params! {
+                        "pid" => p.pid,
+                        "prefix" => LEGACY_GUID_PREFIX,
+                        "url" => &p.link,
+                        "normalized" => normalize_url(&p.link),
+                    },
+                )?;
+                if let Some((id, _)) = res {
+                    conn.exec_drop(
+                        "UPDATE post SET guid=:guid WHERE id=:id",
+                        params! {"guid" => p.identity(), "id" => id},
+                    )?;
+                }
+            }
+            match res {
+                None => new_posts.push((p, hash)),
+                Some((_, Some(old_hash))) if old_hash == hash => {}
+                // stored before we kept hashes, so there's nothing to compare against yet
+                Some((id, None)) => conn.exec_drop(
+                    "UPDATE post SET content_hash=:hash WHERE id=:id",
+                    params! {"hash" => hash, "id" => id},
+                )?,
+                Some((id, Some(_))) => {
+                    let mut tx = conn.start_transaction(TxOpts::default())?;
+                    tx.exec_drop(
+                        r"INSERT INTO post_revision (post_id, title, description, content, revised_at)
+                            SELECT id, title, description, content, :revised_at FROM post WHERE id=:id",
+                        params! {
+                            "id" => id,
+                            "revised_at" => format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S")),
+                        },
+                    )?;
+                    tx.exec_drop(
+                        r"UPDATE post SET url=:url, title=:title, content=:content, description=:description,
+                            image=:image, author=:author, content_hash=:hash, updated_at=:updated_at WHERE id=:id",
+                        params! {
+                            "url" => &p.link,
+                            "title" => &p.title,
+                            "content" => &p.content,
+                            "description" => &p.description,
+                            "image" => &p.enclosure,
+                            "author" => stored_author(p),
+                            "hash" => hash,
+                            // atom and json feeds tell us when the post was updated
+                            "updated_at" => format!("{}", p.updated.unwrap_or(Utc::now()).format("%Y-%m-%d %H:%M:%S")),
+                            "id" => id,
+                        },
+                    )?;
+                    tx.commit()?;
+                }
+            }
+        }
+
+        let insert = conn.prep(
+            r"INSERT IGNORE into post (url, guid, title, content, date_added, description, image, author, pid, content_hash)
+                VALUES (:url, :guid, :title, :content, :date_added, :description, :image, :author, :pid, :content_hash)",
+        )?;
+        let mut inserted = Vec::new();
+        for (p, hash) in new_posts {
+            conn.exec_drop(
+                &insert,
                 params! {
                     "url" => &p.link,
+                    "guid" => p.identity(),
                     "title" => &p.title,
                     "content" => &p.content,
                     "date_added" => format!("{}", &p.date.format("%Y-%m-%d %H:%M:%S")),
                     "description" => &p.description,
                     "image" => &p.enclosure,
+                    "author" => stored_author(p),
                     "pid" => &p.pid,
-                }
-            }),
+                    "content_hash" => hash,
+                },
+            )?;
+            // the same post can show up twice in one fetch, only the first is inserted
+            if conn.affected_rows() > 0 {
+                inserted.push((conn.last_insert_id(), p));
+            }
+        }
+        Ok(inserted)
+    }
+
+    /// load_revisions fills in what the publisher has changed in the post since it was first stored
+    pub async fn load_revisions(&self, post: &mut Post) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            "SELECT title, description, content, revised_at FROM post_revision \
+            WHERE post_id=:id ORDER BY revised_at, id",
+        )?;
+        let versions = conn.exec_map(
+            query,
+            params! {"id" => post.id},
+            |(title, description, content, revised_at): (
+                String,
+                String,
+                Option<String>,
+                NaiveDateTime,
+            )| PostVersion {
+                title,
+                description,
+                content,
+                revised_at: revised_at.and_utc(),
+            },
         )?;
+        post.set_revisions(versions);
         Ok(())
     }
 
-    pub async fn get_post(
-        &self,
-        id: Option<u64>,
-        url: Option<String>,
-    ) -> Result<Post, DetailedError> {
+    /// get_post looks a post up for the user, giving nothing back unless it's from one of
+    /// their subscriptions or one they starred
+    pub async fn get_post(&self, uid: u64, id: u64) -> Result<Option<Post>, DetailedError> {
         let mut conn = self.pool.get_conn().unwrap();
 
-        let (query, params) = match id {
-            Some(i) => {
-                let q = conn.prep("SELECT post.id, post.url, post.title, post.content, post.date_added, post.description, post.image, post.pid, publisher.name from post INNER JOIN publisher ON post.pid=publisher.pid where post.id=:id")?;
-                let p = params! { "id" => i };
-                (q, p)
-            }
-            None => match url {
-                Some(u) => {
-                    let q = conn.prep("SELECT post.id, post.url, post.title, post.content, post.date_added, post.description, post.image, post.pid, publisher.name from post INNER JOIN publisher ON post.pid=publisher.pid where post.url=:url")?;
-                    let p = params! {"url" => u};
-                    (q, p)
-                }
-                None => {
-                    return Err(DetailedError::new_with_message(
-                        "Invalid parameters passed!",
-                    ))
-                }
-            },
-        };
+        let query = conn.prep(
+            r"SELECT post.id, post.url, post.title, post.content, post.date_added, post.description, post.image,
+                post.pid, publisher.name, post.updated_at, post.author FROM post
+                INNER JOIN publisher ON post.pid=publisher.pid
+                WHERE post.id=:id
+                AND (EXISTS (
+                    SELECT 1 FROM subscription INNER JOIN channel ON channel.cid=subscription.cid
+                    WHERE subscription.pid=post.pid AND channel.uid=:uid
+                ) OR EXISTS (
+                    SELECT 1 FROM post_state WHERE post_state.id=post.id AND post_state.uid=:uid AND post_state.starred_at IS NOT NULL
+                ))",
+        )?;
+        let params = params! {"id" => id, "uid" => uid};
 
         let post = conn.exec_map(
             query,
             params,
-            |(id, url, title, content, date_added, description, image, pid, publisher_name): (
-                u64,
-                String,
-                String,
-                Option<String>,
-                NaiveDateTime,
-                String,
-                Option<String>,
-                u64,
-                String,
-            )| {
+            |(
+                id,
+                url,
+                title,
+                content,
+                date_added,
+                description,
+                image,
+                pid,
+                publisher_name,
+                updated_at,
+                author,
+            ): PostRow| {
                 Post {
                     id: id,
                     link: url,
@@ -193,20 +529,19 @@ impl DatabaseConnection {
                     enclosure: image,
                     pid: pid,
                     publisher_name: Some(publisher_name),
+                    author,
+                    guid: None,
+                    updated: updated_at.map(|x| x.and_utc()),
+                    revisions: None,
+                    read: None,
+                    starred: None,
+                    snippet: None,
                 }
             },
         );
 
-        // fails here
         match post {
-            Ok(val) => {
-                let new_val = val.into_iter().next();
-                if let Some(post) = new_val {
-                    Ok(post)
-                } else {
-                    Err(DetailedError::new_with_message("No posts found!"))
-                }
-            }
+            Ok(val) => Ok(val.into_iter().next()),
             Err(e) => Err(DetailedError::new(Box::new(e))),
         }
     }
@@ -216,11 +551,13 @@ impl DatabaseConnection {
 
         let query = conn.prep(
             " \
-                SELECT id, post.url, title, date_added, description, image, post.pid, publisher.name FROM post \
+                SELECT post.id, post.url, title, date_added, description, image, post.pid, publisher.name, updated_at, \
+                post_state.read_at IS NOT NULL, post_state.starred_at IS NOT NULL FROM post \
                 INNER JOIN subscription ON post.pid=subscription.pid \
                 INNER JOIN channel ON channel.cid=subscription.cid \
                 INNER JOIN publisher ON post.pid=publisher.pid \
-                WHERE uid=:uid \
+                LEFT JOIN post_state ON post_state.id=post.id AND post_state.uid=channel.uid \
+                WHERE channel.uid=:uid AND post_state.muted_at IS NULL \
                 ORDER BY date_added DESC \
                 LIMIT :offset, 10;
                 ",
@@ -229,16 +566,19 @@ impl DatabaseConnection {
         Ok(conn.exec_map(
             query,
             params! {"uid" => uid, "offset" => offset},
-            |(id, url, title, date_added, description, image, pid, name): (
-                u64,
-                String,
-                String,
-                NaiveDateTime,
-                String,
-                Option<String>,
-                u64,
-                String,
-            )| {
+            |(
+                id,
+                url,
+                title,
+                date_added,
+                description,
+                image,
+                pid,
+                name,
+                updated_at,
+                read,
+                starred,
+            ): PostListRow| {
                 Post {
                     id: id,
                     link: url,
@@ -249,65 +589,1464 @@ impl DatabaseConnection {
                     enclosure: image,
                     pid: pid,
                     publisher_name: Some(name),
+                    author: None,
+                    guid: None,
+                    updated: updated_at.map(|x| x.and_utc()),
+                    revisions: None,
+                    read: Some(read),
+                    starred: Some(starred),
+                    snippet: None,
                 }
             },
         )?)
     }
 
-    pub async fn get_channels_for_user(&self, uid: u64) -> Result<Vec<Channel>, DetailedError> {
+    /// mark_read marks the user's posts as read. Leaving the post, channel, publisher and cutoff out
+    /// marks everything, and each one given narrows down which posts are marked.
+    /// Posts that were already read keep the time they were first read at. Like get_post, starred posts
+    /// can be marked even once the user unsubscribes from their publisher, though not by channel.
+    pub async fn mark_read(
+        &self,
+        uid: u64,
+        id: Option<u64>,
+        cid: Option<u64>,
+        pid: Option<u64>,
+        before: Option<DateTime<Utc>>,
+    ) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            r"INSERT INTO post_state (uid, id, read_at)
+                SELECT :uid, post.id, :now FROM post
+                WHERE (:id IS NULL OR post.id=:id)
+                AND (:pid IS NULL OR post.pid=:pid)
+                AND (:before IS NULL OR post.date_added<=:before)
+                AND (EXISTS (
+                    SELECT 1 FROM subscription INNER JOIN channel ON channel.cid=subscription.cid
+                    WHERE subscription.pid=post.pid AND channel.uid=:uid
+                    AND (:cid IS NULL OR subscription.cid=:cid)
+                ) OR (:cid IS NULL AND EXISTS (
+                    SELECT 1 FROM post_state AS starred WHERE starred.id=post.id AND starred.uid=:uid AND starred.starred_at IS NOT NULL
+                )))
+            ON DUPLICATE KEY UPDATE read_at=COALESCE(post_state.read_at, VALUES(read_at))",
+        )?;
+        Ok(conn.exec_drop(
+            query,
+            params! {
+                "now" => format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S")),
+                "uid" => uid,
+                "id" => id,
+                "cid" => cid,
+                "pid" => pid,
+                "before" => before.map(|x| format!("{}", x.format("%Y-%m-%d %H:%M:%S"))),
+            },
+        )?)
+    }
+
+    /// mark_unread marks a post the user has read as unread again
+    pub async fn mark_unread(&self, uid: u64, id: u64) -> Result<(), DetailedError> {
         let mut conn = self.pool.get_conn()?;
 
-        let query = conn.prep("SELECT cid, name FROM channel where uid=:uid")?;
+        let query = conn.prep("UPDATE post_state SET read_at=NULL WHERE uid=:uid AND id=:id")?;
+        Ok(conn.exec_drop(query, params! {"uid" => uid, "id" => id})?)
+    }
 
-        let res = conn.exec_map(
+    /// star keeps a post for the user, along with a snapshot of its article.
+    /// Starring a post that's already starred only updates the snapshot.
+    /// It returns how many rows were written, nothing is starred if the post isn't from one of the user's subscriptions.
+    pub async fn star(
+        &self,
+        uid: u64,
+        id: u64,
+        snapshot: Option<String>,
+    ) -> Result<u64, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            r"INSERT INTO post_state (uid, id, starred_at, snapshot)
+                SELECT DISTINCT channel.uid, post.id, :now, :snapshot FROM post
+                INNER JOIN subscription ON post.pid=subscription.pid
+                INNER JOIN channel ON channel.cid=subscription.cid
+                WHERE channel.uid=:uid AND post.id=:id
+            ON DUPLICATE KEY UPDATE starred_at=COALESCE(post_state.starred_at, VALUES(starred_at)),
+                snapshot=COALESCE(VALUES(snapshot), post_state.snapshot)",
+        )?;
+        conn.exec_drop(
             query,
-            params! {"uid" => uid},
-            |(cid, name): (u64, String)| Channel { cid, name },
-        );
+            params! {
+                "now" => format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S")),
+                "snapshot" => snapshot,
+                "uid" => uid,
+                "id" => id,
+            },
+        )?;
+        Ok(conn.affected_rows())
+    }
 
-        match res {
-            Ok(val) => Ok(val),
-            Err(e) => Err(e.into()),
-        }
+    pub async fn unstar(&self, uid: u64, id: u64) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            "UPDATE post_state SET starred_at=NULL, snapshot=NULL WHERE uid=:uid AND id=:id",
+        )?;
+        Ok(conn.exec_drop(query, params! {"uid" => uid, "id" => id})?)
     }
 
-    pub async fn insert_channel_for_user(
+    /// get_starred returns the user's starred posts, most recently starred first.
+    /// The posts' content is the snapshot taken when they were starred.
+    pub async fn get_starred(
         &self,
         uid: u64,
-        name: String,
-    ) -> Result<(), DetailedError> {
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<Post>, DetailedError> {
         let mut conn = self.pool.get_conn()?;
 
-        let query = conn.prep("INSERT INTO channel (uid, name) VALUES (:uid, :name)")?;
+        let query = conn.prep(
+            " \
+                SELECT post.id, post.url, title, post_state.snapshot, date_added, description, image, post.pid, publisher.name, \
+                updated_at, post_state.read_at IS NOT NULL FROM post_state \
+                INNER JOIN post ON post_state.id=post.id \
+                INNER JOIN publisher ON post.pid=publisher.pid \
+                WHERE post_state.uid=:uid AND post_state.starred_at IS NOT NULL \
+                ORDER BY post_state.starred_at DESC \
+                LIMIT :offset, :limit;
+                ",
+        )?;
 
-        Ok(conn.exec_drop(query, params! {"uid" => uid, "name" => name})?)
+        Ok(conn.exec_map(
+            query,
+            params! {"uid" => uid, "offset" => offset, "limit" => limit},
+            |(
+                id,
+                link,
+                title,
+                content,
+                date_added,
+                description,
+                image,
+                pid,
+                name,
+                updated_at,
+                read,
+            ): StarredRow| Post {
+                id,
+                link,
+                title,
+                content,
+                date: date_added.and_utc(),
+                description,
+                enclosure: image,
+                pid,
+                publisher_name: Some(name),
+                author: None,
+                guid: None,
+                updated: updated_at.map(|x| x.and_utc()),
+                revisions: None,
+                read: Some(read),
+                starred: Some(true),
+                snippet: None,
+            },
+        )?)
     }
 
-    pub async fn delete_channel_for_user(&self, uid: u64, cid: u64) -> Result<(), DetailedError> {
+    /// load_post_state fills in whether the user has read and starred the post.
+    /// It returns the snapshot of the post's article if it's starred.
+    pub async fn load_post_state(
+        &self,
+        uid: u64,
+        post: &mut Post,
+    ) -> Result<Option<String>, DetailedError> {
         let mut conn = self.pool.get_conn()?;
 
-        let query = conn.prep("DELETE FROM channel WHERE uid=:uid and cid=:cid")?;
+        let res: Option<(bool, bool, Option<String>)> = conn.exec_first(
+            "SELECT read_at IS NOT NULL, starred_at IS NOT NULL, snapshot FROM post_state WHERE uid=:uid AND id=:id",
+            params! {"uid" => uid, "id" => post.id},
+        )?;
+        let (read, starred, snapshot) = res.unwrap_or((false, false, None));
+        post.read = Some(read);
+        post.starred = Some(starred);
+        Ok(snapshot)
+    }
 
-        Ok(conn.exec_drop(query, params! {"uid" => uid, "cid" => cid})?)
+    /// search finds the posts from the user's subscriptions that match the query, best matches first.
+    /// Instead of their content, the posts come with a snippet of where they matched.
+    pub async fn search(
+        &self,
+        uid: u64,
+        query: &SearchQuery,
+        filters: &SearchFilters,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<Post>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let sql = conn.prep(
+            r"SELECT post.id, post.url, title, content, date_added, description, image, post.pid, publisher.name, updated_at, post.author FROM post
+                INNER JOIN publisher ON post.pid=publisher.pid
+                WHERE post.pid IN (
+                    SELECT subscription.pid FROM subscription
+                    INNER JOIN channel ON channel.cid=subscription.cid
+                    WHERE channel.uid=:uid AND (:cid IS NULL OR subscription.cid=:cid)
+                )
+                AND MATCH(title, description, content) AGAINST (:query IN BOOLEAN MODE)
+                AND (:pid IS NULL OR post.pid=:pid)
+                AND (:from IS NULL OR date_added>=:from)
+                AND (:to IS NULL OR date_added<=:to)
+                ORDER BY MATCH(title, description, content) AGAINST (:query IN BOOLEAN MODE) DESC, date_added DESC
+                LIMIT :offset, :limit",
+        )?;
+        let date =
+            |x: &Option<DateTime<Utc>>| x.map(|x| format!("{}", x.format("%Y-%m-%d %H:%M:%S")));
+
+        Ok(conn.exec_map(
+            sql,
+            params! {
+                "uid" => uid,
+                "cid" => filters.cid,
+                "pid" => filters.pid,
+                "from" => date(&filters.from),
+                "to" => date(&filters.to),
+                "query" => query.boolean_mode(),
+                "offset" => offset,
+                "limit" => limit,
+            },
+            |(
+                id,
+                link,
+                title,
+                content,
+                date_added,
+                description,
+                image,
+                pid,
+                name,
+                updated_at,
+                author,
+            ): PostRow| {
+                // show where the post matched, the title may be the only place it did
+                let snippet = [Some(&description), content.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .map(|x| query.snippet(x))
+                    .find(|x| x.contains("<mark>"))
+                    .unwrap_or_else(|| query.snippet(&description));
+                Post {
+                    id,
+                    link,
+                    title,
+                    content: None,
+                    date: date_added.and_utc(),
+                    description,
+                    enclosure: image,
+                    pid,
+                    publisher_name: Some(name),
+                    author,
+                    guid: None,
+                    updated: updated_at.map(|x| x.and_utc()),
+                    revisions: None,
+                    read: None,
+                    starred: None,
+                    snippet: Some(snippet),
+                }
+            },
+        )?)
     }
 
-    pub async fn get_all_publishers(&self) -> Result<Vec<Subscription>, DetailedError> {
+    /// get_channel_posts returns the stored posts from a channel's subscriptions, newest first.
+    /// A smart channel's posts are the ones matching its query instead.
+    pub async fn get_channel_posts(
+        &self,
+        cid: u64,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<Post>, DetailedError> {
+        if let Some((uid, query)) = self.get_smart_query(cid).await? {
+            return self
+                .get_smart_posts(uid, &query, None, offset, limit, false)
+                .await;
+        }
         let mut conn = self.pool.get_conn()?;
-        Ok(conn.query_map(
-            "SELECT pid, url FROM publisher",
-            |(pid, url): (u64, String)| Subscription {
-                cid: 0,
-                pid: Some(pid),
-                url: url,
-                name: String::from("_"),
+
+        let query = conn.prep(
+            " \
+                SELECT post.id, post.url, title, date_added, description, image, post.pid, publisher.name, updated_at FROM post \
+                INNER JOIN subscription ON post.pid=subscription.pid \
+                INNER JOIN channel ON channel.cid=subscription.cid \
+                INNER JOIN publisher ON post.pid=publisher.pid \
+                LEFT JOIN post_state ON post_state.id=post.id AND post_state.uid=channel.uid \
+                WHERE subscription.cid=:cid AND post_state.muted_at IS NULL \
+                ORDER BY date_added DESC \
+                LIMIT :offset, :limit;
+                ",
+        )?;
+
+        Ok(conn.exec_map(
+            query,
+            params! {"cid" => cid, "offset" => offset, "limit" => limit},
+            |(id, link, title, date_added, description, image, pid, name, updated_at): (
+                u64,
+                String,
+                String,
+                NaiveDateTime,
+                String,
+                Option<String>,
+                u64,
+                String,
+                Option<NaiveDateTime>,
+            )| Post {
+                id,
+                link,
+                title,
+                content: None,
+                date: date_added.and_utc(),
+                description,
+                enclosure: image,
+                pid,
+                publisher_name: Some(name),
+                author: None,
+                guid: None,
+                updated: updated_at.map(|x| x.and_utc()),
+                revisions: None,
+                read: None,
+                starred: None,
+                snippet: None,
             },
         )?)
     }
-}
 
-impl Clone for DatabaseConnection {
-    fn clone(&self) -> Self {
-        let rf = self.pool.clone();
-        DatabaseConnection { pool: rf }
+    /// get_channel_posts_after gets the channel's posts that were stored after the given post, oldest first
+    pub async fn get_channel_posts_after(
+        &self,
+        cid: u64,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<Post>, DetailedError> {
+        if let Some((uid, query)) = self.get_smart_query(cid).await? {
+            return self
+                .get_smart_posts(uid, &query, Some(after), 0, limit, false)
+                .await;
+        }
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            " \
+                SELECT post.id, post.url, title, date_added, description, image, post.pid, publisher.name, updated_at FROM post \
+                INNER JOIN subscription ON post.pid=subscription.pid \
+                INNER JOIN channel ON channel.cid=subscription.cid \
+                INNER JOIN publisher ON post.pid=publisher.pid \
+                LEFT JOIN post_state ON post_state.id=post.id AND post_state.uid=channel.uid \
+                WHERE subscription.cid=:cid AND post.id>:after AND post_state.muted_at IS NULL \
+                ORDER BY post.id \
+                LIMIT :limit;
+                ",
+        )?;
+
+        Ok(conn.exec_map(
+            query,
+            params! {"cid" => cid, "after" => after, "limit" => limit},
+            |(id, link, title, date_added, description, image, pid, name, updated_at): (
+                u64,
+                String,
+                String,
+                NaiveDateTime,
+                String,
+                Option<String>,
+                u64,
+                String,
+                Option<NaiveDateTime>,
+            )| Post {
+                id,
+                link,
+                title,
+                content: None,
+                date: date_added.and_utc(),
+                description,
+                enclosure: image,
+                pid,
+                publisher_name: Some(name),
+                author: None,
+                guid: None,
+                updated: updated_at.map(|x| x.and_utc()),
+                revisions: None,
+                read: None,
+                starred: None,
+                snippet: None,
+            },
+        )?)
+    }
+
+    /// get_latest_post_id is the id of the last post stored, or 0 if there aren't any
+    pub async fn get_latest_post_id(&self) -> Result<u64, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let id: Option<Option<u64>> = conn.query_first("SELECT MAX(id) FROM post")?;
+        Ok(id.flatten().unwrap_or(0))
+    }
+
+    /// get_channel_feed_posts gets the channel's latest posts, with their content, to publish as a feed.
+    /// Posts the owner's filter rules muted are left out.
+    pub async fn get_channel_feed_posts(
+        &self,
+        cid: u64,
+        limit: u64,
+    ) -> Result<Vec<Post>, DetailedError> {
+        if let Some((uid, query)) = self.get_smart_query(cid).await? {
+            return self
+                .get_smart_posts(uid, &query, None, 0, limit, true)
+                .await;
+        }
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            " \
+                SELECT post.id, post.url, title, content, date_added, description, image, post.pid, publisher.name, updated_at, post.author FROM post \
+                INNER JOIN subscription ON post.pid=subscription.pid \
+                INNER JOIN channel ON channel.cid=subscription.cid \
+                INNER JOIN publisher ON post.pid=publisher.pid \
+                LEFT JOIN post_state ON post_state.id=post.id AND post_state.uid=channel.uid \
+                WHERE subscription.cid=:cid AND post_state.muted_at IS NULL \
+                ORDER BY date_added DESC \
+                LIMIT :limit;
+                ",
+        )?;
+
+        Ok(conn.exec_map(
+            query,
+            params! {"cid" => cid, "limit" => limit},
+            |(
+                id,
+                link,
+                title,
+                content,
+                date_added,
+                description,
+                image,
+                pid,
+                name,
+                updated_at,
+                author,
+            ): PostRow| Post {
+                id,
+                link,
+                title,
+                content,
+                date: date_added.and_utc(),
+                description,
+                enclosure: image,
+                pid,
+                publisher_name: Some(name),
+                author,
+                guid: None,
+                updated: updated_at.map(|x| x.and_utc()),
+                revisions: None,
+                read: None,
+                starred: None,
+                snippet: None,
+            },
+        )?)
     }
+
+    /// get_smart_query returns the query of a smart channel along with who it belongs to,
+    /// or nothing if the channel is a normal one
+    async fn get_smart_query(&self, cid: u64) -> Result<Option<(u64, SmartQuery)>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let res: Option<(u64, Option<String>)> = conn.exec_first(
+            "SELECT uid, query FROM channel WHERE cid=:cid",
+            params! {"cid" => cid},
+        )?;
+        match res {
+            Some((uid, Some(query))) => match serde_json::from_str(&query) {
+                Ok(query) => Ok(Some((uid, query))),
+                Err(e) => Err(DetailedError::new(Box::new(e))),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// get_smart_posts evaluates a smart channel's query against the posts from the user's subscriptions,
+    /// newest first. Starred posts are included even if the user has since unsubscribed from their publisher.
+    /// Given a post id to start after, only posts stored since are returned, oldest first.
+    pub async fn get_smart_posts(
+        &self,
+        uid: u64,
+        query: &SmartQuery,
+        after: Option<u64>,
+        offset: u64,
+        limit: u64,
+        with_content: bool,
+    ) -> Result<Vec<Post>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let sql = conn.prep(
+            r"SELECT post.id, post.url, title, IF(:with_content, content, NULL) AS content, date_added, description, image,
+                post.pid, publisher.name, updated_at, post.author,
+                post_state.read_at IS NOT NULL AS is_read, post_state.starred_at IS NOT NULL AS is_starred FROM post
+                INNER JOIN publisher ON post.pid=publisher.pid
+                LEFT JOIN post_state ON post_state.id=post.id AND post_state.uid=:uid
+                WHERE (post.pid IN (
+                    SELECT subscription.pid FROM subscription
+                    INNER JOIN channel ON channel.cid=subscription.cid
+                    WHERE channel.uid=:uid
+                ) OR post_state.starred_at IS NOT NULL)
+                AND (:keywords IS NULL OR MATCH(title, description, content) AGAINST (:keywords IN BOOLEAN MODE))
+                AND (:pids='' OR FIND_IN_SET(post.pid, :pids))
+                AND (:since IS NULL OR date_added>=:since)
+                AND (NOT :starred OR post_state.starred_at IS NOT NULL)
+                AND (NOT :unread OR post_state.read_at IS NULL)
+                AND (:after IS NULL OR post.id>:after)
+                AND post_state.muted_at IS NULL
+                ORDER BY IF(:after IS NULL, date_added, NULL) DESC, post.id
+                LIMIT :offset, :limit",
+        )?;
+        let keywords = query
+            .keywords
+            .as_deref()
+            .map(|x| SearchQuery::parse(x).boolean_mode());
+        let pids = query
+            .pids
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        let since = query.days.map(|x| {
+            let since = Utc::now() - Duration::days(x.into());
+            format!("{}", since.format("%Y-%m-%d %H:%M:%S"))
+        });
+
+        Ok(conn.exec_map(
+            sql,
+            params! {
+                "with_content" => with_content,
+                "uid" => uid,
+                "keywords" => keywords,
+                "pids" => pids,
+                "since" => since,
+                "starred" => query.starred,
+                "unread" => query.unread,
+                "after" => after,
+                "offset" => offset,
+                "limit" => limit,
+            },
+            post_with_state,
+        )?)
+    }
+
+    /// get_stream returns the posts in a Reader API stream, newest first unless the filters say otherwise.
+    /// Starred streams include posts from publishers the user has since unsubscribed from.
+    pub async fn get_stream(
+        &self,
+        uid: u64,
+        filters: &StreamFilters,
+        offset: u64,
+        limit: u64,
+        with_content: bool,
+    ) -> Result<Vec<Post>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            r"SELECT post.id, post.url, title, IF(:with_content, content, NULL) AS content, date_added, description, image,
+                post.pid, publisher.name, updated_at, post.author,
+                post_state.read_at IS NOT NULL AS is_read, post_state.starred_at IS NOT NULL AS is_starred FROM post
+                INNER JOIN publisher ON post.pid=publisher.pid
+                LEFT JOIN post_state ON post_state.id=post.id AND post_state.uid=:uid
+                WHERE (post.pid IN (
+                    SELECT subscription.pid FROM subscription
+                    INNER JOIN channel ON channel.cid=subscription.cid
+                    WHERE channel.uid=:uid AND (:cid IS NULL OR channel.cid=:cid)
+                ) OR (:starred AND :cid IS NULL AND post_state.starred_at IS NOT NULL))
+                AND (:pid IS NULL OR post.pid=:pid)
+                AND (NOT :starred OR post_state.starred_at IS NOT NULL)
+                AND (:read IS NULL OR (post_state.read_at IS NOT NULL)=:read)
+                AND (:since IS NULL OR date_added>=:since)
+                AND (:until IS NULL OR date_added<:until)
+                AND post_state.muted_at IS NULL
+                ORDER BY IF(:oldest_first, date_added, NULL) ASC, date_added DESC, post.id
+                LIMIT :offset, :limit",
+        )?;
+
+        Ok(conn.exec_map(
+            query,
+            params! {
+                "with_content" => with_content,
+                "uid" => uid,
+                "cid" => filters.cid,
+                "pid" => filters.pid,
+                "starred" => filters.starred,
+                "read" => filters.read,
+                "since" => filters.since.map(|x| format!("{}", x.format("%Y-%m-%d %H:%M:%S"))),
+                "until" => filters.until.map(|x| format!("{}", x.format("%Y-%m-%d %H:%M:%S"))),
+                "oldest_first" => filters.oldest_first,
+                "offset" => offset,
+                "limit" => limit,
+            },
+            post_with_state,
+        )?)
+    }
+
+    /// get_posts_by_ids returns the posts the user can see out of the given ids, newest first.
+    /// Muted posts are left out unless the user starred them.
+    pub async fn get_posts_by_ids(
+        &self,
+        uid: u64,
+        ids: &[u64],
+    ) -> Result<Vec<Post>, DetailedError> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            r"SELECT post.id, post.url, title, content, date_added, description, image,
+                post.pid, publisher.name, updated_at, post.author,
+                post_state.read_at IS NOT NULL AS is_read, post_state.starred_at IS NOT NULL AS is_starred FROM post
+                INNER JOIN publisher ON post.pid=publisher.pid
+                LEFT JOIN post_state ON post_state.id=post.id AND post_state.uid=:uid
+                WHERE FIND_IN_SET(post.id, :ids)
+                AND (post.pid IN (
+                    SELECT subscription.pid FROM subscription
+                    INNER JOIN channel ON channel.cid=subscription.cid
+                    WHERE channel.uid=:uid
+                ) OR post_state.starred_at IS NOT NULL)
+                AND (post_state.muted_at IS NULL OR post_state.starred_at IS NOT NULL)
+                ORDER BY date_added DESC",
+        )?;
+        let ids = ids
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+
+        Ok(conn.exec_map(query, params! {"uid" => uid, "ids" => ids}, post_with_state)?)
+    }
+
+    /// get_posts_by_id pages through the posts the user can see in the order they were stored.
+    /// Given since_id, it returns the posts after it oldest first. Otherwise it returns the posts
+    /// before max_id, or the latest posts, newest first.
+    pub async fn get_posts_by_id(
+        &self,
+        uid: u64,
+        since_id: Option<u64>,
+        max_id: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<Post>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            r"SELECT post.id, post.url, title, content, date_added, description, image,
+                post.pid, publisher.name, updated_at, post.author,
+                post_state.read_at IS NOT NULL AS is_read, post_state.starred_at IS NOT NULL AS is_starred FROM post
+                INNER JOIN publisher ON post.pid=publisher.pid
+                LEFT JOIN post_state ON post_state.id=post.id AND post_state.uid=:uid
+                WHERE (post.pid IN (
+                    SELECT subscription.pid FROM subscription
+                    INNER JOIN channel ON channel.cid=subscription.cid
+                    WHERE channel.uid=:uid
+                ) OR post_state.starred_at IS NOT NULL)
+                AND (:since_id IS NULL OR post.id>:since_id)
+                AND (:max_id IS NULL OR post.id<:max_id)
+                AND post_state.muted_at IS NULL
+                ORDER BY IF(:since_id IS NULL, NULL, post.id) ASC, post.id DESC
+                LIMIT :limit",
+        )?;
+
+        Ok(conn.exec_map(
+            query,
+            params! {"uid" => uid, "since_id" => since_id, "max_id" => max_id, "limit" => limit},
+            post_with_state,
+        )?)
+    }
+
+    /// count_posts is how many posts the user can see, the same ones get_posts_by_id pages through
+    pub async fn count_posts(&self, uid: u64) -> Result<u64, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let count: Option<u64> = conn.exec_first(
+            r"SELECT COUNT(*) FROM post
+                LEFT JOIN post_state ON post_state.id=post.id AND post_state.uid=:uid
+                WHERE (post.pid IN (
+                    SELECT subscription.pid FROM subscription
+                    INNER JOIN channel ON channel.cid=subscription.cid
+                    WHERE channel.uid=:uid
+                ) OR post_state.starred_at IS NOT NULL)
+                AND post_state.muted_at IS NULL",
+            params! {"uid" => uid},
+        )?;
+        Ok(count.unwrap_or(0))
+    }
+
+    /// get_published_channel gets a channel to publish as a feed.
+    /// A channel with a feed token is only returned when given the hash of the same token.
+    pub async fn get_published_channel(
+        &self,
+        cid: u64,
+        token_hash: Option<&str>,
+    ) -> Result<Option<Channel>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let res: Option<(String, bool)> = conn.exec_first(
+            r"SELECT name, feed_token IS NOT NULL FROM channel
+                WHERE cid=:cid AND (feed_token IS NULL OR feed_token=:token_hash)",
+            params! {"cid" => cid, "token_hash" => token_hash},
+        )?;
+        Ok(res.map(|(name, private)| Channel {
+            cid,
+            name,
+            private,
+            query: None,
+        }))
+    }
+
+    /// set_feed_token sets or clears the hash of the token needed to read a channel's published feed.
+    /// It returns false if the user doesn't have the channel.
+    pub async fn set_feed_token(
+        &self,
+        uid: u64,
+        cid: u64,
+        token_hash: Option<&str>,
+    ) -> Result<bool, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        if !self.owns_channel(uid, cid).await? {
+            return Ok(false);
+        }
+        conn.exec_drop(
+            "UPDATE channel SET feed_token=:token_hash WHERE cid=:cid",
+            params! {"token_hash" => token_hash, "cid" => cid},
+        )?;
+        Ok(true)
+    }
+
+    /// owns_channel checks the channel is one of the user's
+    pub async fn owns_channel(&self, uid: u64, cid: u64) -> Result<bool, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let owned: Option<u64> = conn.exec_first(
+            "SELECT cid FROM channel WHERE uid=:uid AND cid=:cid",
+            params! {"uid" => uid, "cid" => cid},
+        )?;
+        Ok(owned.is_some())
+    }
+
+    /// subscribes_to checks the publisher is in one of the user's channels
+    pub async fn subscribes_to(&self, uid: u64, pid: u64) -> Result<bool, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let subscribed: Option<u64> = conn.exec_first(
+            r"SELECT subscription.pid FROM subscription
+                INNER JOIN channel ON channel.cid=subscription.cid
+                WHERE channel.uid=:uid AND subscription.pid=:pid LIMIT 1",
+            params! {"uid" => uid, "pid" => pid},
+        )?;
+        Ok(subscribed.is_some())
+    }
+
+    pub async fn get_channels_for_user(&self, uid: u64) -> Result<Vec<Channel>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn
+            .prep("SELECT cid, name, feed_token IS NOT NULL, query FROM channel where uid=:uid")?;
+
+        let res = conn.exec_map(
+            query,
+            params! {"uid" => uid},
+            |(cid, name, private, query): (u64, String, bool, Option<String>)| {
+                Channel {
+                    cid,
+                    name,
+                    private,
+                    // a query that can't be read anymore shows up as a normal, empty channel
+                    query: query.and_then(|x| serde_json::from_str(&x).ok()),
+                }
+            },
+        );
+
+        match res {
+            Ok(val) => Ok(val),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// insert_channel_for_user creates a channel, which is a smart channel if it's given a query
+    pub async fn insert_channel_for_user(
+        &self,
+        uid: u64,
+        name: String,
+        smart_query: Option<&SmartQuery>,
+    ) -> Result<u64, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query =
+            conn.prep("INSERT INTO channel (uid, name, query) VALUES (:uid, :name, :query)")?;
+        let smart_query = match smart_query.map(serde_json::to_string).transpose() {
+            Ok(val) => val,
+            Err(e) => return Err(DetailedError::new(Box::new(e))),
+        };
+
+        conn.exec_drop(
+            query,
+            params! {"uid" => uid, "name" => name, "query" => smart_query},
+        )?;
+        Ok(conn.last_insert_id())
+    }
+
+    pub async fn delete_channel_for_user(&self, uid: u64, cid: u64) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep("DELETE FROM channel WHERE uid=:uid and cid=:cid")?;
+
+        Ok(conn.exec_drop(query, params! {"uid" => uid, "cid" => cid})?)
+    }
+
+    /// apply_filters runs the filter rules of everyone subscribed to the new posts,
+    /// hiding or marking as read the posts their rules match
+    pub async fn apply_filters(&self, posts: &[(u64, &Post)]) -> Result<(), DetailedError> {
+        if posts.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.pool.get_conn()?;
+
+        let mut pids: Vec<String> = posts.iter().map(|(_, p)| p.pid.to_string()).collect();
+        pids.sort();
+        pids.dedup();
+        // a rule comes back once for each of the publishers it covers
+        let query = conn.prep(
+            r"SELECT DISTINCT filter_rule.fid, filter_rule.uid, filter_rule.cid, filter_rule.pid, kind, pattern, field, action,
+                subscription.pid AS covers FROM filter_rule
+                INNER JOIN channel ON channel.uid=filter_rule.uid
+                INNER JOIN subscription ON subscription.cid=channel.cid
+                WHERE (filter_rule.cid IS NULL OR filter_rule.cid=channel.cid)
+                AND FIND_IN_SET(subscription.pid, :pids)",
+        )?;
+        let rules: Vec<(u64, u64, Option<FilterRule>)> =
+            conn.exec_map(query, params! {"pids" => pids.join(",")}, |mut row: Row| {
+                let uid = row.take("uid").unwrap();
+                let covers = row.take("covers").unwrap();
+                (uid, covers, filter_rule(&mut row))
+            })?;
+
+        let mut muted = Vec::new();
+        for (uid, covers, rule) in rules {
+            // rules that no longer compile were checked when they were made, so just skip them
+            let filter = match rule.map(|x| x.compile()) {
+                Some(Ok(val)) => val,
+                _ => continue,
+            };
+            for (id, post) in posts {
+                if post.pid == covers && filter.matches(post) {
+                    muted.push((uid, *id, filter.rule().action));
+                }
+            }
+        }
+
+        let now = format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S"));
+        conn.exec_batch(
+            r"INSERT INTO post_state (uid, id, read_at, muted_at) VALUES (:uid, :id, :read_at, :muted_at)
+            ON DUPLICATE KEY UPDATE read_at=COALESCE(post_state.read_at, VALUES(read_at)),
+                muted_at=COALESCE(post_state.muted_at, VALUES(muted_at))",
+            muted.iter().map(|(uid, id, action)| {
+                params! {
+                    "uid" => uid,
+                    "id" => id,
+                    "read_at" => (*action == FilterAction::Read).then_some(&now),
+                    "muted_at" => (*action == FilterAction::Hide).then_some(&now),
+                }
+            }),
+        )?;
+        Ok(())
+    }
+
+    pub async fn get_filters(&self, uid: u64) -> Result<Vec<FilterRule>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            "SELECT fid, cid, pid, kind, pattern, field, action FROM filter_rule WHERE uid=:uid",
+        )?;
+        let rules: Vec<Option<FilterRule>> =
+            conn.exec_map(query, params! {"uid" => uid}, |mut row: Row| {
+                filter_rule(&mut row)
+            })?;
+        Ok(rules.into_iter().flatten().collect())
+    }
+
+    /// insert_filter stores a filter rule for the user, it only applies to posts ingested from now on
+    pub async fn insert_filter(&self, uid: u64, rule: &FilterRule) -> Result<u64, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            r"INSERT INTO filter_rule (uid, cid, pid, kind, pattern, field, action)
+                VALUES (:uid, :cid, :pid, :kind, :pattern, :field, :action)",
+        )?;
+        conn.exec_drop(
+            query,
+            params! {
+                "uid" => uid,
+                "cid" => rule.cid,
+                "pid" => rule.pid,
+                "kind" => to_column(&rule.kind),
+                "pattern" => &rule.pattern,
+                "field" => to_column(&rule.field),
+                "action" => to_column(&rule.action),
+            },
+        )?;
+        Ok(conn.last_insert_id())
+    }
+
+    pub async fn delete_filter(&self, uid: u64, fid: u64) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep("DELETE FROM filter_rule WHERE uid=:uid AND fid=:fid")?;
+        Ok(conn.exec_drop(query, params! {"uid" => uid, "fid" => fid})?)
+    }
+
+    /// preview_filter returns which of the user's recent posts the filter would have hit, newest first.
+    /// Only the latest `recent` posts are checked.
+    pub async fn preview_filter(
+        &self,
+        uid: u64,
+        filter: &Filter,
+        recent: u64,
+    ) -> Result<Vec<Post>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            r"SELECT post.id, post.url, title, content, date_added, description, image, post.pid, publisher.name, updated_at, post.author FROM post
+                INNER JOIN publisher ON post.pid=publisher.pid
+                WHERE post.pid IN (
+                    SELECT subscription.pid FROM subscription
+                    INNER JOIN channel ON channel.cid=subscription.cid
+                    WHERE channel.uid=:uid AND (:cid IS NULL OR subscription.cid=:cid)
+                )
+                AND (:pid IS NULL OR post.pid=:pid)
+                ORDER BY date_added DESC
+                LIMIT :recent",
+        )?;
+        let posts = conn.exec_map(
+            query,
+            params! {
+                "uid" => uid,
+                "cid" => filter.rule().cid,
+                "pid" => filter.rule().pid,
+                "recent" => recent,
+            },
+            |(
+                id,
+                link,
+                title,
+                content,
+                date_added,
+                description,
+                image,
+                pid,
+                name,
+                updated_at,
+                author,
+            ): PostRow| Post {
+                id,
+                link,
+                title,
+                content,
+                date: date_added.and_utc(),
+                description,
+                enclosure: image,
+                pid,
+                publisher_name: Some(name),
+                author,
+                guid: None,
+                updated: updated_at.map(|x| x.and_utc()),
+                revisions: None,
+                read: None,
+                starred: None,
+                snippet: None,
+            },
+        )?;
+        Ok(posts
+            .into_iter()
+            .filter(|x| filter.matches(x))
+            // the content was only needed to check the rule
+            .map(|x| Post { content: None, ..x })
+            .collect())
+    }
+
+    /// prune_posts deletes the posts that have outlived their publisher's retention policy.
+    /// Every subscriber can override the default, so a publisher's posts are kept as long as
+    /// its most generous subscriber wants them. Starred posts are never deleted.
+    /// It returns how many posts were deleted.
+    pub async fn prune_posts(&self, default: &RetentionPolicy) -> Result<u64, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        // no limit (0) is mapped to :forever so MAX picks it over any real limit
+        let query = conn.prep(
+            r"DELETE post FROM post
+                INNER JOIN (
+                    SELECT id, ROW_NUMBER() OVER (PARTITION BY pid ORDER BY date_added DESC, id DESC) AS newest FROM post
+                ) ranked ON ranked.id=post.id
+                LEFT JOIN (
+                    SELECT pid,
+                        MAX(COALESCE(NULLIF(COALESCE(retention_days, :days), 0), :forever)) AS days,
+                        MAX(COALESCE(NULLIF(COALESCE(retention_posts, :posts), 0), :forever)) AS posts
+                    FROM subscription GROUP BY pid
+                ) policy ON policy.pid=post.pid
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM post_state WHERE post_state.id=post.id AND post_state.starred_at IS NOT NULL
+                )
+                AND (
+                    (COALESCE(policy.days, NULLIF(:days, 0), :forever) < :forever
+                        AND post.date_added < DATE_SUB(:now, INTERVAL COALESCE(policy.days, :days) DAY))
+                    OR (COALESCE(policy.posts, NULLIF(:posts, 0), :forever) < :forever
+                        AND ranked.newest > COALESCE(policy.posts, :posts))
+                )",
+        )?;
+        conn.exec_drop(
+            query,
+            params! {
+                "days" => default.days,
+                "posts" => default.posts,
+                "forever" => i32::MAX,
+                "now" => format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S")),
+            },
+        )?;
+        Ok(conn.affected_rows())
+    }
+
+    /// set_retention overrides the user's retention policy for one of their publishers,
+    /// limits left as None go back to the default. It returns false if the user isn't subscribed to it.
+    pub async fn set_retention(
+        &self,
+        uid: u64,
+        pid: u64,
+        policy: &RetentionPolicy,
+    ) -> Result<bool, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            r"UPDATE subscription INNER JOIN channel ON channel.cid=subscription.cid
+                SET subscription.retention_days=:days, subscription.retention_posts=:posts
+                WHERE channel.uid=:uid AND subscription.pid=:pid",
+            params! {"days" => policy.days, "posts" => policy.posts, "uid" => uid, "pid" => pid},
+        )?;
+        if conn.affected_rows() > 0 {
+            return Ok(true);
+        }
+        // the policy may already have been set to the same values
+        self.subscribes_to(uid, pid).await
+    }
+
+    /// create_user makes an account, returning its uid or nothing if the username is taken
+    pub async fn create_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<Option<u64>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "INSERT IGNORE INTO user (username, password_hash) VALUES (:username, :password_hash)",
+            params! {"username" => username, "password_hash" => password_hash},
+        )?;
+        if conn.affected_rows() == 0 {
+            return Ok(None);
+        }
+        Ok(Some(conn.last_insert_id()))
+    }
+
+    /// get_login returns the uid and password hash of the user with the username
+    pub async fn get_login(&self, username: &str) -> Result<Option<(u64, String)>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        Ok(conn.exec_first(
+            "SELECT uid, password_hash FROM user WHERE username=:username",
+            params! {"username" => username},
+        )?)
+    }
+
+    /// get_username returns the name of the user, for showing who's logged in
+    pub async fn get_username(&self, uid: u64) -> Result<Option<String>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        Ok(conn.exec_first(
+            "SELECT username FROM user WHERE uid=:uid",
+            params! {"uid" => uid},
+        )?)
+    }
+
+    /// set_fever_key turns the Fever api on for the user with the hash of the given key, or off with none
+    pub async fn set_fever_key(
+        &self,
+        uid: u64,
+        key_hash: Option<&str>,
+    ) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        Ok(conn.exec_drop(
+            "UPDATE user SET fever_key=:key_hash WHERE uid=:uid",
+            params! {"key_hash" => key_hash, "uid" => uid},
+        )?)
+    }
+
+    /// get_fever_user returns who the Fever api key with the hash belongs to, if anyone
+    pub async fn get_fever_user(&self, key_hash: &str) -> Result<Option<u64>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        Ok(conn.exec_first(
+            "SELECT uid FROM user WHERE fever_key=:key_hash",
+            params! {"key_hash" => key_hash},
+        )?)
+    }
+
+    pub async fn create_session(
+        &self,
+        uid: u64,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        Ok(conn.exec_drop(
+            r"INSERT INTO session (token_hash, uid, created_at, expires_at)
+                VALUES (:token_hash, :uid, :created_at, :expires_at)",
+            params! {
+                "token_hash" => token_hash,
+                "uid" => uid,
+                "created_at" => format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S")),
+                "expires_at" => format!("{}", expires_at.format("%Y-%m-%d %H:%M:%S")),
+            },
+        )?)
+    }
+
+    /// get_session_user returns who the session belongs to, or nothing if it doesn't exist or has expired
+    pub async fn get_session_user(&self, token_hash: &str) -> Result<Option<u64>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        Ok(conn.exec_first(
+            "SELECT uid FROM session WHERE token_hash=:token_hash AND expires_at>:now",
+            params! {
+                "token_hash" => token_hash,
+                "now" => format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S")),
+            },
+        )?)
+    }
+
+    pub async fn delete_session(&self, token_hash: &str) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        Ok(conn.exec_drop(
+            "DELETE FROM session WHERE token_hash=:token_hash",
+            params! {"token_hash" => token_hash},
+        )?)
+    }
+
+    /// delete_expired_sessions clears out old logins, returning how many there were
+    pub async fn delete_expired_sessions(&self) -> Result<u64, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "DELETE FROM session WHERE expires_at<=:now",
+            params! {"now" => format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S"))},
+        )?;
+        Ok(conn.affected_rows())
+    }
+
+    /// create_api_token stores a new token for the user, returning its id
+    pub async fn create_api_token(
+        &self,
+        uid: u64,
+        name: &str,
+        scopes: &[Scope],
+        token_hash: &str,
+    ) -> Result<u64, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let scopes = match serde_json::to_string(scopes) {
+            Ok(val) => val,
+            Err(e) => return Err(DetailedError::new(Box::new(e))),
+        };
+        conn.exec_drop(
+            r"INSERT INTO api_token (uid, name, token_hash, scopes, created_at)
+                VALUES (:uid, :name, :token_hash, :scopes, :created_at)",
+            params! {
+                "uid" => uid,
+                "name" => name,
+                "token_hash" => token_hash,
+                "scopes" => scopes,
+                "created_at" => format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S")),
+            },
+        )?;
+        Ok(conn.last_insert_id())
+    }
+
+    pub async fn get_api_tokens(&self, uid: u64) -> Result<Vec<ApiToken>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn.prep(
+            "SELECT tid, name, scopes, created_at, last_used_at FROM api_token WHERE uid=:uid ORDER BY created_at DESC",
+        )?;
+        Ok(conn.exec_map(
+            query,
+            params! {"uid" => uid},
+            |(tid, name, scopes, created_at, last_used_at): (
+                u64,
+                String,
+                String,
+                NaiveDateTime,
+                Option<NaiveDateTime>,
+            )| ApiToken {
+                tid,
+                name,
+                scopes: serde_json::from_str(&scopes).unwrap_or_default(),
+                created_at: created_at.and_utc(),
+                last_used_at: last_used_at.map(|x| x.and_utc()),
+            },
+        )?)
+    }
+
+    /// delete_api_token revokes one of the user's tokens, returning false if they don't have it
+    pub async fn delete_api_token(&self, uid: u64, tid: u64) -> Result<bool, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "DELETE FROM api_token WHERE uid=:uid AND tid=:tid",
+            params! {"uid" => uid, "tid" => tid},
+        )?;
+        Ok(conn.affected_rows() > 0)
+    }
+
+    /// use_api_token looks up who a token belongs to and what it's allowed to do,
+    /// noting that it's been used
+    pub async fn use_api_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<(u64, Vec<Scope>)>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let res: Option<(u64, u64, String)> = conn.exec_first(
+            "SELECT tid, uid, scopes FROM api_token WHERE token_hash=:token_hash",
+            params! {"token_hash" => token_hash},
+        )?;
+        let (tid, uid, scopes) = match res {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        conn.exec_drop(
+            "UPDATE api_token SET last_used_at=:now WHERE tid=:tid",
+            params! {
+                "now" => format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S")),
+                "tid" => tid,
+            },
+        )?;
+        Ok(Some((
+            uid,
+            serde_json::from_str(&scopes).unwrap_or_default(),
+        )))
+    }
+
+    /// get_all_publishers returns the publishers that are due to be fetched,
+    /// leaving out disabled ones and failing ones that are still backing off
+    pub async fn get_all_publishers(&self) -> Result<Vec<Subscription>, DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+        let pubs = conn.query_map(
+            format!("SELECT pid, url, etag, last_modified, {STATUS_COLUMNS} FROM publisher"),
+            |mut row: Row| Subscription {
+                cid: 0,
+                pid: row.take("pid"),
+                url: row.take("url").unwrap(),
+                name: String::from("_"),
+                site_url: None,
+                etag: row.take("etag").unwrap(),
+                last_modified: row.take("last_modified").unwrap(),
+                status: Some(feed_status(&mut row)),
+                retention: None,
+            },
+        )?;
+        let now = Utc::now();
+        Ok(pubs
+            .into_iter()
+            .filter(|x| x.status.as_ref().is_some_and(|x| x.is_due(now)))
+            .collect())
+    }
+
+    /// update_fetch_results records how each publisher's fetch went.
+    /// Successful fetches save the cache validators so the next fetch can be a conditional one,
+    /// failed ones count towards the publisher being disabled.
+    pub async fn update_fetch_results(&self, results: &[FetchResult]) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+        let now = format!("{}", Utc::now().format("%Y-%m-%d %H:%M:%S"));
+
+        conn.exec_batch(
+            r"UPDATE publisher SET etag=:etag, last_modified=:last_modified, last_fetched=:now, last_success=:now,
+                consecutive_failures=0, last_status=:status, last_error=NULL, disabled=FALSE WHERE pid=:pid",
+            results.iter().filter(|r| r.error.is_none()).map(|r| {
+                params! {
+                    "etag" => &r.etag,
+                    "last_modified" => &r.last_modified,
+                    "now" => &now,
+                    "status" => r.status,
+                    "pid" => r.pid,
+                }
+            }),
+        )?;
+        // mysql applies the assignments in order, so disabled sees the incremented count
+        conn.exec_batch(
+            r"UPDATE publisher SET last_fetched=:now, consecutive_failures=consecutive_failures+1,
+                last_status=:status, last_error=:error, disabled=(consecutive_failures >= :max) WHERE pid=:pid",
+            results.iter().filter(|r| r.error.is_some()).map(|r| {
+                params! {
+                    "now" => &now,
+                    "status" => r.status,
+                    // keep it within the column's size
+                    "error" => r.error.as_ref().map(|x| x.chars().take(500).collect::<String>()),
+                    "max" => MAX_CONSECUTIVE_FAILURES,
+                    "pid" => r.pid,
+                }
+            }),
+        )?;
+        Ok(())
+    }
+
+    /// enable_publisher clears a publisher's failures so it gets fetched again
+    pub async fn enable_publisher(&self, pid: u64) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let query = conn
+            .prep("UPDATE publisher SET disabled=FALSE, consecutive_failures=0 WHERE pid=:pid")?;
+
+        Ok(conn.exec_drop(query, params! {"pid" => pid})?)
+    }
+
+    /// move_publisher points a publisher at the url its feed has permanently moved to.
+    /// If we already have a publisher at that url, the two are merged into it:
+    /// subscriptions, filter rules and posts move over, skipping any the other publisher already has.
+    pub async fn move_publisher(&self, pid: u64, url: &str) -> Result<(), DetailedError> {
+        let mut conn = self.pool.get_conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        let existing: Option<u64> = tx.exec_first(
+            "SELECT pid FROM publisher WHERE url=:url",
+            params! {"url" => url},
+        )?;
+        match existing {
+            None => tx.exec_drop(
+                "UPDATE publisher SET url=:url WHERE pid=:pid",
+                params! {"url" => url, "pid" => pid},
+            )?,
+            Some(other) if other == pid => {}
+            Some(other) => {
+                let params = params! {"pid" => pid, "other" => other};
+                tx.exec_drop(
+                    r"INSERT IGNORE INTO subscription (cid, pid, retention_days, retention_posts)
+                        SELECT cid, :other, retention_days, retention_posts FROM subscription WHERE pid=:pid",
+                    &params,
+                )?;
+                tx.exec_drop("DELETE FROM subscription WHERE pid=:pid", &params)?;
+                tx.exec_drop("UPDATE filter_rule SET pid=:other WHERE pid=:pid", &params)?;
+                // posts the other publisher already has are left behind and deleted with the rest,
+                // so what users did with them is carried over to the other publisher's copy first
+                tx.exec_drop("UPDATE IGNORE post SET pid=:other WHERE pid=:pid", &params)?;
+                tx.exec_drop(
+                    r"INSERT INTO post_state (uid, id, read_at, starred_at, snapshot, muted_at)
+                        SELECT state.uid, kept.id, state.read_at, state.starred_at, state.snapshot, state.muted_at
+                        FROM post_state state
+                        INNER JOIN post old ON state.id=old.id
+                        INNER JOIN post kept ON kept.pid=:other AND kept.guid=old.guid
+                        WHERE old.pid=:pid
+                    ON DUPLICATE KEY UPDATE read_at=COALESCE(post_state.read_at, VALUES(read_at)),
+                        starred_at=COALESCE(post_state.starred_at, VALUES(starred_at)),
+                        snapshot=COALESCE(post_state.snapshot, VALUES(snapshot)),
+                        muted_at=COALESCE(post_state.muted_at, VALUES(muted_at))",
+                    &params,
+                )?;
+                tx.exec_drop("DELETE FROM post WHERE pid=:pid", &params)?;
+                tx.exec_drop("DELETE FROM publisher WHERE pid=:pid", &params)?;
+            }
+        }
+        Ok(tx.commit()?)
+    }
+}
+
+// marks the guids migrations/004_post_guid.sql gave posts stored before they had one,
+// the rest of the guid is the post's url
+const LEGACY_GUID_PREFIX: &str = "legacy:";
+
+// the longest author the post table holds
+const MAX_AUTHOR_LENGTH: usize = 200;
+
+// the publisher columns that make up a FeedStatus
+const STATUS_COLUMNS: &str =
+    "last_fetched, last_success, consecutive_failures, last_status, last_error, disabled";
+
+fn feed_status(row: &mut Row) -> FeedStatus {
+    let last_fetched: Option<NaiveDateTime> = row.take("last_fetched").unwrap();
+    let last_success: Option<NaiveDateTime> = row.take("last_success").unwrap();
+    FeedStatus {
+        last_fetched: last_fetched.map(|x| x.and_utc()),
+        last_success: last_success.map(|x| x.and_utc()),
+        consecutive_failures: row.take("consecutive_failures").unwrap(),
+        last_status: row.take("last_status").unwrap(),
+        last_error: row.take("last_error").unwrap(),
+        disabled: row.take("disabled").unwrap(),
+    }
+}
+
+// stored_author cuts the post's author down to fit the post table's column
+fn stored_author(post: &Post) -> Option<String> {
+    post.author
+        .as_ref()
+        .map(|x| x.chars().take(MAX_AUTHOR_LENGTH).collect())
+}
+
+// post_with_state reads a full post along with whether the user has read and starred it
+fn post_with_state(mut row: Row) -> Post {
+    let date_added: NaiveDateTime = row.take("date_added").unwrap();
+    let updated_at: Option<NaiveDateTime> = row.take("updated_at").unwrap();
+    Post {
+        id: row.take("id").unwrap(),
+        link: row.take("url").unwrap(),
+        title: row.take("title").unwrap(),
+        content: row.take("content").unwrap(),
+        date: date_added.and_utc(),
+        description: row.take("description").unwrap(),
+        enclosure: row.take("image").unwrap(),
+        pid: row.take("pid").unwrap(),
+        publisher_name: row.take("name"),
+        author: row.take("author").unwrap(),
+        guid: None,
+        updated: updated_at.map(|x| x.and_utc()),
+        revisions: None,
+        read: row.take("is_read"),
+        starred: row.take("is_starred"),
+        snippet: None,
+    }
+}
+
+impl Clone for DatabaseConnection {
+    fn clone(&self) -> Self {
+        let rf = self.pool.clone();
+        DatabaseConnection { pool: rf }
+    }
+}
+
+// filter_rule reads a rule out of a filter_rule row, or nothing if it has a kind, field or action we don't know
+fn filter_rule(row: &mut Row) -> Option<FilterRule> {
+    Some(FilterRule {
+        fid: row.take("fid")?,
+        cid: row.take("cid")?,
+        pid: row.take("pid")?,
+        kind: from_column(row.take("kind")?)?,
+        pattern: row.take("pattern")?,
+        field: from_column(row.take("field")?)?,
+        action: from_column(row.take("action")?)?,
+    })
+}
+
+// to_column stores one of our enums as the same name it has in json
+fn to_column<T: Serialize>(val: &T) -> Option<String> {
+    match serde_json::to_value(val) {
+        Ok(serde_json::Value::String(val)) => Some(val),
+        _ => None,
+    }
+}
+
+fn from_column<T: DeserializeOwned>(val: String) -> Option<T> {
+    serde_json::from_value(serde_json::Value::String(val)).ok()
 }
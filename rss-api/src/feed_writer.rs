@@ -0,0 +1,231 @@
+use crate::{escape_xml, Post};
+use chrono::Utc;
+
+/// FeedMeta describes the feed being written
+pub struct FeedMeta<'a> {
+    pub title: &'a str,
+    pub description: &'a str,
+    // where the feed itself can be fetched from
+    pub self_url: &'a str,
+}
+
+/// write_rss renders the posts as an RSS 2.0 feed
+pub fn write_rss(meta: &FeedMeta, posts: &[Post]) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <rss version=\"2.0\" xmlns:content=\"http://purl.org/rss/1.0/modules/content/\" \
+        xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n",
+    );
+    out.push_str(&format!(
+        "<title>{}</title>\n<link>{}</link>\n<description>{}</description>\n\
+        <atom:link href=\"{1}\" rel=\"self\" type=\"application/rss+xml\"/>\n<lastBuildDate>{}</lastBuildDate>\n",
+        escape_xml(meta.title),
+        escape_xml(meta.self_url),
+        escape_xml(meta.description),
+        Utc::now().to_rfc2822()
+    ));
+
+    for post in posts {
+        out.push_str("<item>\n");
+        out.push_str(&format!(
+            "<title>{}</title>\n<link>{}</link>\n<guid isPermaLink=\"false\">{}</guid>\n<pubDate>{}</pubDate>\n",
+            escape_xml(&post.title),
+            escape_xml(&post.link),
+            escape_xml(&entry_id(meta, post)),
+            post.date.to_rfc2822()
+        ));
+        out.push_str(&format!(
+            "<description>{}</description>\n",
+            escape_xml(&post.description)
+        ));
+        if let Some(content) = &post.content {
+            out.push_str(&format!(
+                "<content:encoded>{}</content:encoded>\n",
+                escape_xml(content)
+            ));
+        }
+        if let Some(author) = author(post) {
+            out.push_str(&format!(
+                "<dc:creator>{}</dc:creator>\n",
+                escape_xml(author)
+            ));
+        }
+        if let Some(image) = &post.enclosure {
+            // we don't know the size of the image, which rss allows to be 0
+            out.push_str(&format!(
+                "<enclosure url=\"{}\" length=\"0\" type=\"{}\"/>\n",
+                escape_xml(image),
+                image_type(image)
+            ));
+        }
+        out.push_str("</item>\n");
+    }
+    out.push_str("</channel>\n</rss>\n");
+    out
+}
+
+/// write_atom renders the posts as an Atom 1.0 feed
+pub fn write_atom(meta: &FeedMeta, posts: &[Post]) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+    );
+    // the feed was last updated when its newest post was
+    let updated = posts
+        .iter()
+        .map(|x| x.updated.unwrap_or(x.date))
+        .max()
+        .unwrap_or(Utc::now());
+    out.push_str(&format!(
+        "<title>{}</title>\n<subtitle>{}</subtitle>\n<id>{}</id>\n<link rel=\"self\" href=\"{2}\"/>\n<updated>{}</updated>\n",
+        escape_xml(meta.title),
+        escape_xml(meta.description),
+        escape_xml(meta.self_url),
+        updated.to_rfc3339()
+    ));
+
+    for post in posts {
+        out.push_str("<entry>\n");
+        out.push_str(&format!(
+            "<title>{}</title>\n<id>{}</id>\n<link rel=\"alternate\" href=\"{}\"/>\n<published>{}</published>\n<updated>{}</updated>\n",
+            escape_xml(&post.title),
+            escape_xml(&entry_id(meta, post)),
+            escape_xml(&post.link),
+            post.date.to_rfc3339(),
+            post.updated.unwrap_or(post.date).to_rfc3339()
+        ));
+        // atom needs an author on every entry when the feed doesn't have one
+        out.push_str(&format!(
+            "<author><name>{}</name></author>\n",
+            escape_xml(author(post).unwrap_or(meta.title))
+        ));
+        if let Some(publisher) = &post.publisher_name {
+            out.push_str(&format!(
+                "<source><title>{}</title></source>\n",
+                escape_xml(publisher)
+            ));
+        }
+        out.push_str(&format!(
+            "<summary type=\"html\">{}</summary>\n",
+            escape_xml(&post.description)
+        ));
+        if let Some(content) = &post.content {
+            out.push_str(&format!(
+                "<content type=\"html\">{}</content>\n",
+                escape_xml(content)
+            ));
+        }
+        if let Some(image) = &post.enclosure {
+            out.push_str(&format!(
+                "<link rel=\"enclosure\" href=\"{}\" type=\"{}\"/>\n",
+                escape_xml(image),
+                image_type(image)
+            ));
+        }
+        out.push_str("</entry>\n");
+    }
+    out.push_str("</feed>\n");
+    out
+}
+
+// entry_id is a permanent id for the post within this feed
+fn entry_id(meta: &FeedMeta, post: &Post) -> String {
+    format!("{}#post-{}", meta.self_url, post.id)
+}
+
+// the post's own author if the feed gave one, otherwise its publisher
+fn author(post: &Post) -> Option<&str> {
+    post.author.as_deref().or(post.publisher_name.as_deref())
+}
+
+// image_type guesses the mime type of an enclosure from its extension
+fn image_type(url: &str) -> &'static str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let extension = path.rsplit('.').next().unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        _ => "image/jpeg",
+    }
+}
+
+#[cfg(test)]
+mod feed_writer_tests {
+    use super::*;
+    use crate::rss_parser::parse_feed;
+    use crate::Subscription;
+    use chrono::TimeZone;
+
+    fn posts() -> Vec<Post> {
+        let mut first = Post::new_link("https://example.com/a?x=1&y=2".to_string());
+        first.id = 2;
+        first.title = "Fish & <Chips>".to_string();
+        first.date = Utc.with_ymd_and_hms(2024, 3, 2, 10, 0, 0).unwrap();
+        first.publisher_name = Some("Example".to_string());
+        first.enclosure = Some("https://example.com/image.png".to_string());
+        first.content = Some("<p>The whole post</p>".to_string());
+
+        let mut second = Post::new_link("https://example.org/b".to_string());
+        second.id = 1;
+        second.date = Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap();
+        second.publisher_name = Some("Other".to_string());
+        vec![first, second]
+    }
+
+    fn meta() -> FeedMeta<'static> {
+        FeedMeta {
+            title: "Tech",
+            description: "Posts from the Tech channel",
+            self_url: "http://localhost:3000/channel/1/feed.xml",
+        }
+    }
+
+    // checks the feed reads back with our own parser
+    async fn read_back(data: &str) -> Vec<Post> {
+        let sub = Subscription {
+            pid: Some(1),
+            ..Default::default()
+        };
+        match parse_feed(data, &sub).await {
+            Ok(feed) => {
+                assert!(feed.skipped.is_empty());
+                feed.posts
+            }
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_rss() {
+        let data = write_rss(&meta(), &posts());
+        assert!(data.contains("<dc:creator>Example</dc:creator>"));
+        assert!(data.contains(
+            "<enclosure url=\"https://example.com/image.png\" length=\"0\" type=\"image/png\"/>"
+        ));
+
+        let read = read_back(&data).await;
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].title, "Fish & <Chips>");
+        assert_eq!(read[0].link, "https://example.com/a?x=1&y=2");
+        assert_eq!(read[0].date, posts()[0].date);
+        assert_eq!(
+            read[0].enclosure,
+            Some("https://example.com/image.png".to_string())
+        );
+        assert_eq!(read[1].link, "https://example.org/b");
+    }
+
+    #[tokio::test]
+    async fn test_write_atom() {
+        let data = write_atom(&meta(), &posts());
+        assert!(data.contains("<source><title>Other</title></source>"));
+        assert!(data.contains("<updated>2024-03-02T10:00:00+00:00</updated>"));
+
+        let read = read_back(&data).await;
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].title, "Fish & <Chips>");
+        assert_eq!(read[0].link, "https://example.com/a?x=1&y=2");
+        assert_eq!(read[1].date, posts()[1].date);
+    }
+}
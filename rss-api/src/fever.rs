@@ -0,0 +1,547 @@
+use crate::auth::{self, AuthUser};
+use crate::database::DatabaseConnection;
+use crate::greader::{self, StreamFilters};
+use crate::server_error;
+use crate::Post;
+use axum::{
+    body::Bytes,
+    extract::{FromRef, RawQuery, State},
+    http::StatusCode,
+    routing::post,
+    Json, Router,
+};
+use chrono::DateTime;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+const API_VERSION: u32 = 3;
+// how many items a page of items has, as the Fever api specifies
+const ITEMS_PAGE_SIZE: u64 = 50;
+// the most ids unread_item_ids and saved_item_ids list
+const MAX_ITEM_IDS: u64 = 10000;
+
+/// router serves the Fever api at /fever/, for clients that don't speak anything else.
+/// A user turns it on with /fever/key, after which clients log in with md5(username:password) as their api_key.
+/// Channels are groups and publishers are feeds.
+pub fn router<S>() -> Router<S>
+where
+    DatabaseConnection: FromRef<S>,
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/fever", post(fever).get(fever))
+        .route("/fever/", post(fever).get(fever))
+        .route("/fever/key", post(set_key).delete(delete_key))
+}
+
+/// api_key is the key a Fever client logs in with.
+/// Fever keys are md5 by definition, only a hash of it is stored
+pub fn api_key(username: &str, password: &str) -> String {
+    format!("{:x}", Md5::digest(format!("{username}:{password}")))
+}
+
+#[derive(Deserialize)]
+struct FeverKeyRequest {
+    // the user's password, which the key is made from
+    password: String,
+}
+
+// FeverResponse holds every section the api has, only the ones asked for are filled in
+#[derive(Serialize, Default)]
+struct FeverResponse {
+    api_version: u32,
+    auth: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_refreshed_on_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<Group>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    feeds: Option<Vec<Feed>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    feeds_groups: Option<Vec<FeedsGroup>>,
+    // we don't keep favicons, but clients expect the section
+    #[serde(skip_serializing_if = "Option::is_none")]
+    favicons: Option<Vec<()>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items: Option<Vec<Item>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_items: Option<u64>,
+    // comma separated ids
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unread_item_ids: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    saved_item_ids: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Group {
+    id: u64,
+    title: String,
+}
+
+#[derive(Serialize)]
+struct FeedsGroup {
+    group_id: u64,
+    // comma separated pids
+    feed_ids: String,
+}
+
+#[derive(Serialize)]
+struct Feed {
+    id: u64,
+    favicon_id: u64,
+    title: String,
+    url: String,
+    site_url: String,
+    is_spark: u8,
+    last_updated_on_time: i64,
+}
+
+#[derive(Serialize)]
+struct Item {
+    id: u64,
+    feed_id: u64,
+    title: String,
+    author: String,
+    html: String,
+    url: String,
+    is_saved: u8,
+    is_read: u8,
+    created_on_time: i64,
+}
+
+// fever answers every api call. What to return is in the query string (?api&items&since_id=1),
+// and the api_key and any mark to make are in the form body. Clients that send a wrong key get auth 0.
+async fn fever(
+    State(dbconn): State<DatabaseConnection>,
+    RawQuery(query): RawQuery,
+    body: Bytes,
+) -> Result<Json<FeverResponse>, (StatusCode, String)> {
+    let params: HashMap<String, String> =
+        url::form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .chain(url::form_urlencoded::parse(&body))
+            .map(|(key, val)| (key.into_owned(), val.into_owned()))
+            .collect();
+    let mut res = FeverResponse {
+        api_version: API_VERSION,
+        ..Default::default()
+    };
+
+    let key = params
+        .get("api_key")
+        .map(|x| x.to_lowercase())
+        .unwrap_or_default();
+    let uid = match dbconn.get_fever_user(&auth::hash_token(&key)).await {
+        Ok(Some(uid)) => uid,
+        Ok(None) => return Ok(Json(res)),
+        Err(e) => return Err(server_error(e, 0)),
+    };
+    res.auth = 1;
+
+    // marks come first, so the ids sent back already include them
+    if let Some(mark) = params.get("mark") {
+        mark_as(&dbconn, uid, mark, &params).await?;
+    }
+    fill_sections(&dbconn, uid, &params, &mut res).await?;
+    Ok(Json(res))
+}
+
+// set_key turns the Fever api on, once the user has confirmed their password
+async fn set_key(
+    State(dbconn): State<DatabaseConnection>,
+    user: AuthUser,
+    Json(payload): Json<FeverKeyRequest>,
+) -> Result<(), (StatusCode, String)> {
+    let login = match dbconn.get_username(user.uid).await {
+        Ok(Some(username)) => dbconn
+            .get_login(&username)
+            .await
+            .map(|x| x.map(|(_, hash)| (username, hash))),
+        other => other.map(|_| None),
+    };
+    let username = match login {
+        Ok(Some((username, hash))) if auth::verify_password(&hash, &payload.password) => username,
+        Ok(_) => return Err((StatusCode::UNAUTHORIZED, "Wrong password".to_string())),
+        Err(e) => return Err(server_error(e, user.uid)),
+    };
+    match dbconn
+        .set_fever_key(
+            user.uid,
+            Some(&auth::hash_token(&api_key(&username, &payload.password))),
+        )
+        .await
+    {
+        Ok(()) => Ok(()),
+        Err(e) => Err(server_error(e, user.uid)),
+    }
+}
+
+async fn delete_key(
+    State(dbconn): State<DatabaseConnection>,
+    user: AuthUser,
+) -> Result<(), (StatusCode, String)> {
+    match dbconn.set_fever_key(user.uid, None).await {
+        Ok(()) => Ok(()),
+        Err(e) => Err(server_error(e, user.uid)),
+    }
+}
+
+// mark_as makes a mark=item|feed|group as=read|unread|saved|unsaved call.
+// Feeds and groups can only be marked as read, everything up to the before= time.
+// Group 0 is every feed.
+async fn mark_as(
+    dbconn: &DatabaseConnection,
+    uid: u64,
+    mark: &str,
+    params: &HashMap<String, String>,
+) -> Result<(), (StatusCode, String)> {
+    let id = match id_param(params, "id")? {
+        Some(val) => val,
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Missing required `id` field".to_string(),
+            ))
+        }
+    };
+    let before = match params.get("before").map(|x| x.parse::<i64>()) {
+        Some(Ok(val)) => DateTime::from_timestamp(val, 0),
+        Some(Err(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Invalid `before` field".to_string(),
+            ))
+        }
+        None => None,
+    };
+
+    let res = match (mark, params.get("as").map(|x| x.as_str())) {
+        ("item", Some("read")) => dbconn.mark_read(uid, Some(id), None, None, None).await,
+        ("item", Some("unread")) => dbconn.mark_unread(uid, id).await,
+        ("item", Some("saved")) => dbconn.star(uid, id, None).await.map(|_| ()),
+        ("item", Some("unsaved")) => dbconn.unstar(uid, id).await,
+        ("feed", Some("read")) => dbconn.mark_read(uid, None, None, Some(id), before).await,
+        ("group", Some("read")) => {
+            let cid = Some(id).filter(|x| *x > 0);
+            dbconn.mark_read(uid, None, cid, None, before).await
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Can't mark a {mark} like that"),
+            ))
+        }
+    };
+    res.map_err(|e| server_error(e, uid))
+}
+
+// fill_sections fills in each section the query string asks for
+async fn fill_sections(
+    dbconn: &DatabaseConnection,
+    uid: u64,
+    params: &HashMap<String, String>,
+    res: &mut FeverResponse,
+) -> Result<(), (StatusCode, String)> {
+    let subs = dbconn
+        .get_subbed_for_user(uid)
+        .await
+        .map_err(|e| server_error(e, uid))?;
+    res.last_refreshed_on_time = Some(
+        subs.iter()
+            .filter_map(|x| x.status.as_ref().and_then(|x| x.last_fetched))
+            .max()
+            .map(|x| x.timestamp())
+            .unwrap_or(0),
+    );
+
+    if params.contains_key("groups") || params.contains_key("feeds") {
+        let labels = greader::labels(dbconn, uid)
+            .await
+            .map_err(|e| server_error(e, uid))?;
+        res.feeds_groups = Some(
+            labels
+                .iter()
+                .map(|(channel, subs)| FeedsGroup {
+                    group_id: channel.cid,
+                    feed_ids: join(subs.iter().filter_map(|x| x.pid)),
+                })
+                .collect(),
+        );
+        if params.contains_key("groups") {
+            res.groups = Some(
+                labels
+                    .iter()
+                    .map(|(channel, _)| Group {
+                        id: channel.cid,
+                        title: channel.name.clone(),
+                    })
+                    .collect(),
+            );
+        }
+        if params.contains_key("feeds") {
+            let mut feeds = BTreeMap::new();
+            for sub in labels.iter().flat_map(|(_, subs)| subs) {
+                let pid = sub.pid.unwrap_or_default();
+                feeds.entry(pid).or_insert_with(|| Feed {
+                    id: pid,
+                    favicon_id: 0,
+                    title: sub.name.clone(),
+                    url: sub.url.clone(),
+                    site_url: sub.site_url.clone().unwrap_or_default(),
+                    is_spark: 0,
+                    last_updated_on_time: sub
+                        .status
+                        .as_ref()
+                        .and_then(|x| x.last_success)
+                        .map(|x| x.timestamp())
+                        .unwrap_or(0),
+                });
+            }
+            res.feeds = Some(feeds.into_values().collect());
+        }
+    }
+    if params.contains_key("favicons") {
+        res.favicons = Some(vec![]);
+    }
+
+    if params.contains_key("items") {
+        let posts = match params.get("with_ids") {
+            Some(ids) => {
+                let ids = match parse_ids(ids) {
+                    Some(val) if val.len() as u64 <= ITEMS_PAGE_SIZE => val,
+                    Some(_) => {
+                        return Err((
+                            StatusCode::BAD_REQUEST,
+                            format!("Can't get more than {ITEMS_PAGE_SIZE} items at a time"),
+                        ))
+                    }
+                    None => {
+                        return Err((
+                            StatusCode::BAD_REQUEST,
+                            "Invalid `with_ids` field".to_string(),
+                        ))
+                    }
+                };
+                dbconn.get_posts_by_ids(uid, &ids).await
+            }
+            None => {
+                let since_id = id_param(params, "since_id")?;
+                let max_id = id_param(params, "max_id")?;
+                dbconn
+                    .get_posts_by_id(uid, since_id, max_id, ITEMS_PAGE_SIZE)
+                    .await
+            }
+        };
+        let page = match posts {
+            Ok(posts) => dbconn.count_posts(uid).await.map(|total| (posts, total)),
+            Err(e) => Err(e),
+        };
+        let (posts, total) = page.map_err(|e| server_error(e, uid))?;
+        res.items = Some(posts.iter().map(item).collect());
+        res.total_items = Some(total);
+    }
+
+    if params.contains_key("unread_item_ids") {
+        let filters = StreamFilters {
+            read: Some(false),
+            ..Default::default()
+        };
+        let posts = dbconn
+            .get_stream(uid, &filters, 0, MAX_ITEM_IDS, false)
+            .await
+            .map_err(|e| server_error(e, uid))?;
+        res.unread_item_ids = Some(join(posts.iter().map(|x| x.id)));
+    }
+    if params.contains_key("saved_item_ids") {
+        let filters = StreamFilters {
+            starred: true,
+            ..Default::default()
+        };
+        let posts = dbconn
+            .get_stream(uid, &filters, 0, MAX_ITEM_IDS, false)
+            .await
+            .map_err(|e| server_error(e, uid))?;
+        res.saved_item_ids = Some(join(posts.iter().map(|x| x.id)));
+    }
+    Ok(())
+}
+
+fn item(post: &Post) -> Item {
+    Item {
+        id: post.id,
+        feed_id: post.pid,
+        title: post.title.clone(),
+        author: post.author.clone().unwrap_or_default(),
+        html: post.content.clone().unwrap_or(post.description.clone()),
+        url: post.link.clone(),
+        is_saved: post.starred.unwrap_or(false).into(),
+        is_read: post.read.unwrap_or(false).into(),
+        created_on_time: post.date.timestamp(),
+    }
+}
+
+fn join(ids: impl Iterator<Item = u64>) -> String {
+    ids.map(|x| x.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// parse_ids reads a comma separated list of ids, or nothing if one of them isn't a number
+fn parse_ids(ids: &str) -> Option<Vec<u64>> {
+    ids.split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse::<u64>().ok())
+        .collect()
+}
+
+fn id_param(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<u64>, (StatusCode, String)> {
+    match params.get(name).map(|x| x.parse::<u64>()) {
+        Some(Ok(val)) => Ok(Some(val)),
+        Some(Err(_)) => Err((StatusCode::BAD_REQUEST, format!("Invalid `{name}` field"))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod fever_tests {
+    use super::*;
+    use crate::rss_parser;
+    use chrono::Utc;
+    use serde_json::Value;
+
+    #[test]
+    fn test_api_key() {
+        assert_eq!(api_key("arjun", "pass"), "532a8ec204bf4113577873a15ced915c");
+        assert_ne!(api_key("arjun", "pass"), api_key("arjun", "pass2"));
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        assert_eq!(parse_ids("1,2, 30"), Some(vec![1, 2, 30]));
+        assert_eq!(parse_ids(""), Some(vec![]));
+        assert_eq!(parse_ids("1,two"), None);
+    }
+
+    #[test]
+    fn test_response() {
+        // a failed login only says so
+        let res = FeverResponse {
+            api_version: API_VERSION,
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            r#"{"api_version":3,"auth":0}"#
+        );
+
+        let mut post = Post::new_link("https://example.com/a".to_string());
+        post.id = 7;
+        post.starred = Some(true);
+        let item = serde_json::to_value(item(&post)).unwrap();
+        assert_eq!(item["id"], 7);
+        assert_eq!(item["feed_id"], 10000);
+        assert_eq!(item["html"], "Test");
+        assert_eq!(item["is_saved"], 1);
+        assert_eq!(item["is_read"], 0);
+    }
+
+    async fn call(client: &reqwest::Client, url: &str, form: &[(&str, &str)]) -> Value {
+        let res = client.post(url).form(form).send().await.unwrap();
+        assert_eq!(res.status(), 200, "{url}");
+        serde_json::from_str(&res.text().await.unwrap()).unwrap()
+    }
+
+    // runs against the database at localhost:3306, see DatabaseConnection::new
+    #[ignore = "database"]
+    #[tokio::test]
+    async fn test_fever_api() {
+        let dbconn = DatabaseConnection::new();
+        let base = crate::serve_api(router(), dbconn.clone()).await;
+        let client = reqwest::Client::new();
+
+        let username = format!("fever_{}", &auth::new_token()[..10]);
+        let hash = auth::hash_password("correct horse").unwrap();
+        let uid = dbconn.create_user(&username, &hash).await.unwrap().unwrap();
+        let cid = dbconn
+            .insert_channel_for_user(uid, "Tech".to_string(), None)
+            .await
+            .unwrap();
+        dbconn
+            .subscribe(cid, format!("{base}/atom.xml"))
+            .await
+            .unwrap();
+        let feed = rss_parser::get_whole_feed(dbconn.get_subbed(cid).await.unwrap()).await;
+        dbconn.insert_posts(&feed.posts).await.unwrap();
+
+        let key = api_key(&username, "correct horse");
+        let res = call(&client, &format!("{base}/fever/?api"), &[("api_key", &key)]).await;
+        assert_eq!(res["auth"], 0);
+        dbconn
+            .set_fever_key(uid, Some(&auth::hash_token(&key)))
+            .await
+            .unwrap();
+        let res = call(
+            &client,
+            &format!("{base}/fever/?api"),
+            &[("api_key", "nope")],
+        )
+        .await;
+        assert_eq!(res["auth"], 0);
+
+        let url = format!("{base}/fever/?api&groups&feeds");
+        let res = call(&client, &url, &[("api_key", &key)]).await;
+        assert_eq!(res["auth"], 1);
+        assert_eq!(res["groups"][0]["id"], cid);
+        assert_eq!(res["groups"][0]["title"], "Tech");
+        let pid = res["feeds"][0]["id"].as_u64().unwrap();
+        assert_eq!(res["feeds_groups"][0]["feed_ids"], pid.to_string());
+
+        // paging up from the start gets everything, oldest first
+        let url = format!("{base}/fever/?api&items&since_id=0");
+        let res = call(&client, &url, &[("api_key", &key)]).await;
+        let items = res["items"].as_array().unwrap();
+        assert_eq!(items.len(), feed.posts.len());
+        let first = items[0]["id"].as_u64().unwrap();
+        let last = items[items.len() - 1]["id"].as_u64().unwrap();
+        assert!(first < last);
+        let url = format!("{base}/fever/?api&items&max_id={last}");
+        let res = call(&client, &url, &[("api_key", &key)]).await;
+        let items = res["items"].as_array().unwrap();
+        assert_eq!(items.len(), feed.posts.len() - 1);
+        assert!(items[0]["id"].as_u64().unwrap() < last);
+
+        let url = format!("{base}/fever/?api&unread_item_ids&saved_item_ids");
+        let first_id = first.to_string();
+        let form = [
+            ("api_key", key.as_str()),
+            ("mark", "item"),
+            ("as", "saved"),
+            ("id", &first_id),
+        ];
+        let res = call(&client, &url, &form).await;
+        assert_eq!(res["saved_item_ids"], first_id);
+
+        let form = [
+            ("api_key", key.as_str()),
+            ("mark", "feed"),
+            ("as", "read"),
+            ("id", &pid.to_string()),
+            ("before", &Utc::now().timestamp().to_string()),
+        ];
+        let res = call(&client, &url, &form).await;
+        assert_eq!(res["unread_item_ids"], "");
+        let form = [
+            ("api_key", key.as_str()),
+            ("mark", "item"),
+            ("as", "unread"),
+            ("id", &first_id),
+        ];
+        let res = call(&client, &url, &form).await;
+        assert_eq!(res["unread_item_ids"], first_id);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::Post;
+use regex::{Regex, RegexBuilder};
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+
+// longest pattern a rule can have
+const MAX_PATTERN_LENGTH: usize = 255;
+// how big a compiled regex can get, keeps a rule from eating the ingest job's memory
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// FilterRule mutes a user's posts as they come in.
+/// A rule can be narrowed to a channel's subscriptions and to a single publisher.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FilterRule {
+    #[serde(default)]
+    pub fid: u64,
+    // only posts from this channel's subscriptions
+    pub cid: Option<u64>,
+    // only posts from this publisher
+    pub pid: Option<u64>,
+    pub kind: FilterKind,
+    // the keyword or regex, unused by publisher rules
+    #[serde(default)]
+    pub pattern: String,
+    #[serde(default)]
+    pub field: FilterField,
+    #[serde(default)]
+    pub action: FilterAction,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterKind {
+    // a word or phrase, matched as whole words and ignoring case
+    Keyword,
+    Regex,
+    // every post from the rule's publisher
+    Publisher,
+}
+
+/// FilterField is which part of the post a rule looks at
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterField {
+    #[default]
+    Title,
+    // the title, description and content
+    Full,
+}
+
+/// FilterAction is what happens to a post a rule matches
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    // left out of /feed and /all
+    #[default]
+    Hide,
+    // marked as read
+    Read,
+}
+
+/// Filter is a rule that's ready to be checked against posts
+pub struct Filter {
+    rule: FilterRule,
+    regex: Option<Regex>,
+}
+
+impl FilterRule {
+    /// compile checks the rule, returning what's wrong with it if it can't be used
+    pub fn compile(self) -> Result<Filter, String> {
+        if self.pattern.len() > MAX_PATTERN_LENGTH {
+            return Err(format!(
+                "The pattern can't be longer than {MAX_PATTERN_LENGTH} characters"
+            ));
+        }
+        let pattern = match self.kind {
+            FilterKind::Publisher if self.pid.is_none() => {
+                return Err("A publisher rule needs a publisher".to_string())
+            }
+            FilterKind::Publisher => None,
+            _ if self.pattern.trim().is_empty() => {
+                return Err("The rule needs a pattern".to_string())
+            }
+            FilterKind::Keyword => Some(format!(r"\b{}\b", regex::escape(self.pattern.trim()))),
+            FilterKind::Regex => Some(self.pattern.clone()),
+        };
+        let regex = match pattern {
+            Some(pattern) => match RegexBuilder::new(&pattern)
+                .case_insensitive(true)
+                .size_limit(REGEX_SIZE_LIMIT)
+                .build()
+            {
+                Ok(val) => Some(val),
+                Err(e) => return Err(format!("Invalid regex: {e}")),
+            },
+            None => None,
+        };
+        Ok(Filter { rule: self, regex })
+    }
+}
+
+impl Filter {
+    pub fn rule(&self) -> &FilterRule {
+        &self.rule
+    }
+
+    /// matches checks the post against the rule's pattern and publisher.
+    /// Whether the post is in the rule's channel is left to the caller.
+    pub fn matches(&self, post: &Post) -> bool {
+        if self.rule.pid.is_some_and(|x| x != post.pid) {
+            return false;
+        }
+        let regex = match &self.regex {
+            Some(val) => val,
+            None => return true,
+        };
+        if regex.is_match(&post.title) {
+            return true;
+        }
+        self.rule.field == FilterField::Full
+            && [Some(&post.description), post.content.as_ref()]
+                .into_iter()
+                .flatten()
+                .any(|x| regex.is_match(&text(x)))
+    }
+}
+
+// text is what a reader sees of the html, so rules don't match tags and attributes
+fn text(html: &str) -> String {
+    Html::parse_fragment(html)
+        .root_element()
+        .text()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::*;
+
+    fn rule(kind: FilterKind, pattern: &str) -> FilterRule {
+        FilterRule {
+            fid: 0,
+            cid: None,
+            pid: None,
+            kind,
+            pattern: pattern.to_string(),
+            field: FilterField::Title,
+            action: FilterAction::Hide,
+        }
+    }
+
+    fn post(pid: u64, title: &str, content: &str) -> Post {
+        let mut post = Post::new_link("https://example.com/a".to_string());
+        post.pid = pid;
+        post.title = title.to_string();
+        post.set_content(content.to_string());
+        post
+    }
+
+    #[test]
+    fn test_keyword_filter() {
+        let filter = match rule(FilterKind::Keyword, "deal").compile() {
+            Ok(val) => val,
+            Err(error) => panic!("{error}"),
+        };
+        assert!(filter.matches(&post(1, "Today's best DEAL on laptops", "")));
+        // whole words only
+        assert!(!filter.matches(&post(1, "The new deals idealist", "")));
+        // title only, unless the rule says otherwise
+        assert!(!filter.matches(&post(1, "Laptops", "<p>a deal</p>")));
+
+        let mut full = rule(FilterKind::Keyword, "sponsored");
+        full.field = FilterField::Full;
+        let filter = match full.compile() {
+            Ok(val) => val,
+            Err(error) => panic!("{error}"),
+        };
+        assert!(filter.matches(&post(1, "Laptops", "<p>This post is sponsored</p>")));
+        assert!(!filter.matches(&post(1, "Laptops", "<p class=\"sponsored\">Hi</p>")));
+    }
+
+    #[test]
+    fn test_regex_filter() {
+        let mut regex = rule(FilterKind::Regex, r"^\[(ad|promo)\]");
+        regex.pid = Some(2);
+        let filter = match regex.compile() {
+            Ok(val) => val,
+            Err(error) => panic!("{error}"),
+        };
+        assert!(filter.matches(&post(2, "[Promo] Half off", "")));
+        assert!(!filter.matches(&post(2, "Half off [promo]", "")));
+        // other publishers aren't affected
+        assert!(!filter.matches(&post(3, "[Promo] Half off", "")));
+
+        assert!(rule(FilterKind::Regex, "(unclosed").compile().is_err());
+        assert!(rule(FilterKind::Keyword, "  ").compile().is_err());
+    }
+
+    #[test]
+    fn test_publisher_filter() {
+        assert!(rule(FilterKind::Publisher, "").compile().is_err());
+        let mut publisher = rule(FilterKind::Publisher, "");
+        publisher.pid = Some(2);
+        let filter = match publisher.compile() {
+            Ok(val) => val,
+            Err(error) => panic!("{error}"),
+        };
+        assert!(filter.matches(&post(2, "Anything", "")));
+        assert!(!filter.matches(&post(1, "Anything", "")));
+    }
+}
@@ -0,0 +1,840 @@
+use crate::auth::{self, AuthUser};
+use crate::database::DatabaseConnection;
+use crate::logger::DetailedError;
+use crate::server_error;
+use crate::{Channel, Post, Subscription};
+use axum::{
+    extract::{FromRef, Path, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+    Form, Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+// the long form of an item id, which ends in the post's id as 16 hex digits
+const ITEM_ID_PREFIX: &str = "tag:google.com,2005:reader/item/";
+const READING_LIST: &str = "user/-/state/com.google/reading-list";
+const STARRED: &str = "user/-/state/com.google/starred";
+const READ: &str = "user/-/state/com.google/read";
+const LABEL_PREFIX: &str = "user/-/label/";
+// default and largest number of items stream/contents returns at a time
+const STREAM_PAGE_SIZE: u64 = 20;
+const MAX_STREAM_PAGE_SIZE: u64 = 1000;
+// clients sync by asking for every unread or starred id at once
+const MAX_IDS_PAGE_SIZE: u64 = 10000;
+
+/// Stream is a list of items a client can ask for, see parse_stream
+#[derive(PartialEq, Debug)]
+pub enum Stream {
+    // every post from the user's subscriptions
+    ReadingList,
+    Starred,
+    Read,
+    // a channel, by its name
+    Label(String),
+    // a publisher, by its pid
+    Feed(u64),
+}
+
+/// StreamFilters narrow down the posts in a stream
+#[derive(Default)]
+pub struct StreamFilters {
+    pub cid: Option<u64>,
+    pub pid: Option<u64>,
+    pub starred: bool,
+    // only read posts, or only unread posts
+    pub read: Option<bool>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub oldest_first: bool,
+}
+
+/// router serves the subset of the Google Reader API that mobile clients need to sync.
+/// Clients log in with /accounts/ClientLogin and send the session back as `Authorization: GoogleLogin auth=<token>`.
+/// Channels are labels, publishers are feeds, and read and starred are the only states.
+pub fn router<S>() -> Router<S>
+where
+    DatabaseConnection: FromRef<S>,
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route(
+            "/accounts/ClientLogin",
+            get(client_login).post(client_login),
+        )
+        .route("/reader/api/0/token", get(token))
+        .route("/reader/api/0/user-info", get(user_info))
+        .route("/reader/api/0/subscription/list", get(subscription_list))
+        .route("/reader/api/0/tag/list", get(tag_list))
+        .route("/reader/api/0/stream/contents", get(stream_contents))
+        .route(
+            "/reader/api/0/stream/contents/*stream",
+            get(stream_contents),
+        )
+        .route("/reader/api/0/stream/items/ids", get(stream_item_ids))
+        .route(
+            "/reader/api/0/stream/items/contents",
+            get(stream_item_contents).post(stream_item_contents),
+        )
+        .route("/reader/api/0/edit-tag", post(edit_tag))
+}
+
+/// parse_stream reads a stream id, like user/-/label/Tech or feed/12.
+/// The user in a user/ stream is ignored, it's always whoever is logged in.
+pub fn parse_stream(id: &str) -> Option<Stream> {
+    if let Some(pid) = id.strip_prefix("feed/") {
+        return pid.parse::<u64>().ok().map(Stream::Feed);
+    }
+    let (_, rest) = id.strip_prefix("user/")?.split_once('/')?;
+    match rest.split_once('/')? {
+        ("label", name) if !name.is_empty() => Some(Stream::Label(name.to_string())),
+        ("state", "com.google/reading-list") => Some(Stream::ReadingList),
+        ("state", "com.google/starred") => Some(Stream::Starred),
+        ("state", "com.google/read") => Some(Stream::Read),
+        _ => None,
+    }
+}
+
+/// item_id is the long form of a post's id, which items are sent with
+pub fn item_id(id: u64) -> String {
+    format!("{ITEM_ID_PREFIX}{id:016x}")
+}
+
+/// parse_item_id reads either form of an item id, the long one or the plain number
+pub fn parse_item_id(id: &str) -> Option<u64> {
+    match id.strip_prefix(ITEM_ID_PREFIX) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        // short ids are signed, so a client may send a big one as negative
+        None => id.parse::<i64>().ok().map(|x| x as u64),
+    }
+}
+
+#[derive(Deserialize)]
+struct ClientLogin {
+    #[serde(rename = "Email")]
+    email: String,
+    #[serde(rename = "Passwd")]
+    passwd: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserInfo {
+    user_id: String,
+    user_name: String,
+    user_profile_id: String,
+}
+
+#[derive(Serialize)]
+struct SubscriptionList {
+    subscriptions: Vec<ReaderSubscription>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReaderSubscription {
+    id: String,
+    title: String,
+    categories: Vec<Category>,
+    url: String,
+    html_url: String,
+}
+
+#[derive(Serialize)]
+struct Category {
+    id: String,
+    label: String,
+}
+
+#[derive(Serialize)]
+struct TagList {
+    tags: Vec<Tag>,
+}
+
+#[derive(Serialize)]
+struct Tag {
+    id: String,
+    // labels are folders, states have no type
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    kind: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+struct StreamContents {
+    id: String,
+    updated: i64,
+    items: Vec<Item>,
+    // what to send as c= for the next page, left out on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    continuation: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    id: String,
+    crawl_time_msec: String,
+    timestamp_usec: String,
+    published: i64,
+    updated: i64,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+    canonical: Vec<Link>,
+    alternate: Vec<Link>,
+    summary: Summary,
+    categories: Vec<String>,
+    origin: Origin,
+}
+
+#[derive(Serialize, Clone)]
+struct Link {
+    href: String,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    content: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Origin {
+    stream_id: String,
+    title: String,
+    html_url: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ItemIds {
+    item_refs: Vec<ItemRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    continuation: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ItemRef {
+    // the short form, a plain number
+    id: String,
+    timestamp_usec: String,
+}
+
+/// Labels are the user's channels along with their subscriptions.
+/// Smart channels are left out, since their posts don't come from subscriptions.
+pub(crate) type Labels = Vec<(Channel, Vec<Subscription>)>;
+
+// client_login checks the user's password and starts a session for the client,
+// answering in the plain text format clients expect
+async fn client_login(
+    State(dbconn): State<DatabaseConnection>,
+    Form(payload): Form<ClientLogin>,
+) -> Result<String, (StatusCode, String)> {
+    let login = dbconn.get_login(&payload.email).await;
+    let uid = match login.map(|x| auth::check_login(x, &payload.passwd)) {
+        Ok(Some(uid)) => uid,
+        Ok(None) => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "Error=BadAuthentication\n".to_string(),
+            ))
+        }
+        Err(e) => return Err(server_error(e, 0)),
+    };
+
+    let token = auth::new_token();
+    let expires_at = Utc::now() + Duration::days(auth::SESSION_DAYS);
+    match dbconn
+        .create_session(uid, &auth::hash_token(&token), expires_at)
+        .await
+    {
+        Ok(()) => Ok(format!("SID={token}\nLSID=null\nAuth={token}\n")),
+        Err(e) => Err(server_error(e, uid)),
+    }
+}
+
+// token is the write token clients send back with edits. The session is in a header
+// rather than a cookie, so there's nothing to forge and any token will do.
+async fn token(_user: AuthUser) -> String {
+    auth::new_token()
+}
+
+async fn user_info(
+    State(dbconn): State<DatabaseConnection>,
+    user: AuthUser,
+) -> Result<Json<UserInfo>, (StatusCode, String)> {
+    match dbconn.get_username(user.uid).await {
+        Ok(Some(username)) => Ok(Json(UserInfo {
+            user_id: user.uid.to_string(),
+            user_name: username,
+            user_profile_id: user.uid.to_string(),
+        })),
+        Ok(None) => Err((StatusCode::UNAUTHORIZED, "Not logged in".to_string())),
+        Err(e) => Err(server_error(e, user.uid)),
+    }
+}
+
+// subscription_list lists each publisher once, with every channel it's in as a category
+async fn subscription_list(
+    State(dbconn): State<DatabaseConnection>,
+    user: AuthUser,
+) -> Result<Json<SubscriptionList>, (StatusCode, String)> {
+    let labels = match labels(&dbconn, user.uid).await {
+        Ok(val) => val,
+        Err(e) => return Err(server_error(e, user.uid)),
+    };
+
+    let mut subscriptions: BTreeMap<u64, ReaderSubscription> = BTreeMap::new();
+    for (channel, subs) in &labels {
+        for sub in subs {
+            let pid = sub.pid.unwrap_or_default();
+            let entry = subscriptions
+                .entry(pid)
+                .or_insert_with(|| ReaderSubscription {
+                    id: format!("feed/{pid}"),
+                    title: sub.name.clone(),
+                    categories: vec![],
+                    url: sub.url.clone(),
+                    html_url: sub.site_url.clone().unwrap_or_default(),
+                });
+            entry.categories.push(Category {
+                id: format!("{LABEL_PREFIX}{}", channel.name),
+                label: channel.name.clone(),
+            });
+        }
+    }
+    Ok(Json(SubscriptionList {
+        subscriptions: subscriptions.into_values().collect(),
+    }))
+}
+
+async fn tag_list(
+    State(dbconn): State<DatabaseConnection>,
+    user: AuthUser,
+) -> Result<Json<TagList>, (StatusCode, String)> {
+    let labels = match labels(&dbconn, user.uid).await {
+        Ok(val) => val,
+        Err(e) => return Err(server_error(e, user.uid)),
+    };
+
+    let mut tags = vec![Tag {
+        id: STARRED.to_string(),
+        kind: None,
+    }];
+    tags.extend(labels.iter().map(|(channel, _)| Tag {
+        id: format!("{LABEL_PREFIX}{}", channel.name),
+        kind: Some("folder"),
+    }));
+    Ok(Json(TagList { tags }))
+}
+
+// stream_contents pages through a stream's items. The stream can be in the path or in s=,
+// and defaults to the reading list.
+async fn stream_contents(
+    State(dbconn): State<DatabaseConnection>,
+    user: AuthUser,
+    path: Option<Path<String>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<StreamContents>, (StatusCode, String)> {
+    let id = match path {
+        Some(Path(val)) => val.trim_start_matches('/').to_string(),
+        None => params.get("s").cloned().unwrap_or(READING_LIST.to_string()),
+    };
+    let labels = match labels(&dbconn, user.uid).await {
+        Ok(val) => val,
+        Err(e) => return Err(server_error(e, user.uid)),
+    };
+    let filters = stream_filters(&id, &params, &labels)?;
+    let offset = number_param(&params, "c")?.unwrap_or(0);
+    let limit = number_param(&params, "n")?
+        .unwrap_or(STREAM_PAGE_SIZE)
+        .min(MAX_STREAM_PAGE_SIZE);
+
+    match dbconn
+        .get_stream(user.uid, &filters, offset, limit, true)
+        .await
+    {
+        Ok(posts) => Ok(Json(StreamContents {
+            id,
+            updated: Utc::now().timestamp(),
+            continuation: continuation(posts.len(), offset, limit),
+            items: posts.iter().map(|x| item(x, &labels)).collect(),
+        })),
+        Err(e) => Err(server_error(e, user.uid)),
+    }
+}
+
+async fn stream_item_ids(
+    State(dbconn): State<DatabaseConnection>,
+    user: AuthUser,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<ItemIds>, (StatusCode, String)> {
+    let id = match params.get("s") {
+        Some(val) => val,
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Missing required `s` field".to_string(),
+            ))
+        }
+    };
+    let labels = match labels(&dbconn, user.uid).await {
+        Ok(val) => val,
+        Err(e) => return Err(server_error(e, user.uid)),
+    };
+    let filters = stream_filters(id, &params, &labels)?;
+    let offset = number_param(&params, "c")?.unwrap_or(0);
+    let limit = number_param(&params, "n")?
+        .unwrap_or(MAX_IDS_PAGE_SIZE)
+        .min(MAX_IDS_PAGE_SIZE);
+
+    match dbconn
+        .get_stream(user.uid, &filters, offset, limit, false)
+        .await
+    {
+        Ok(posts) => Ok(Json(ItemIds {
+            continuation: continuation(posts.len(), offset, limit),
+            item_refs: posts
+                .iter()
+                .map(|x| ItemRef {
+                    id: x.id.to_string(),
+                    timestamp_usec: x.date.timestamp_micros().to_string(),
+                })
+                .collect(),
+        })),
+        Err(e) => Err(server_error(e, user.uid)),
+    }
+}
+
+// stream_item_contents gets the items for the ids a client found with stream_item_ids, given as i=
+async fn stream_item_contents(
+    State(dbconn): State<DatabaseConnection>,
+    user: AuthUser,
+    Form(params): Form<Vec<(String, String)>>,
+) -> Result<Json<StreamContents>, (StatusCode, String)> {
+    let ids = item_ids(&params)?;
+    if ids.len() as u64 > MAX_STREAM_PAGE_SIZE {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Can't get more than {MAX_STREAM_PAGE_SIZE} items at a time"),
+        ));
+    }
+    let res = match labels(&dbconn, user.uid).await {
+        Ok(labels) => dbconn
+            .get_posts_by_ids(user.uid, &ids)
+            .await
+            .map(|posts| (labels, posts)),
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok((labels, posts)) => Ok(Json(StreamContents {
+            id: READING_LIST.to_string(),
+            updated: Utc::now().timestamp(),
+            items: posts.iter().map(|x| item(x, &labels)).collect(),
+            continuation: None,
+        })),
+        Err(e) => Err(server_error(e, user.uid)),
+    }
+}
+
+// edit_tag adds (a=) and removes (r=) the read and starred states on the items given as i=.
+// Labels are channels, which items can't be moved between, so they're ignored.
+async fn edit_tag(
+    State(dbconn): State<DatabaseConnection>,
+    user: AuthUser,
+    Form(params): Form<Vec<(String, String)>>,
+) -> Result<&'static str, (StatusCode, String)> {
+    let ids = item_ids(&params)?;
+    let tags: Vec<(Stream, bool)> = params
+        .iter()
+        .filter_map(|(key, val)| match key.as_str() {
+            "a" => parse_stream(val).map(|x| (x, true)),
+            "r" => parse_stream(val).map(|x| (x, false)),
+            _ => None,
+        })
+        .collect();
+
+    for id in ids {
+        for (tag, add) in &tags {
+            let res = match (tag, add) {
+                (Stream::Read, true) => {
+                    dbconn.mark_read(user.uid, Some(id), None, None, None).await
+                }
+                (Stream::Read, false) => dbconn.mark_unread(user.uid, id).await,
+                (Stream::Starred, true) => dbconn.star(user.uid, id, None).await.map(|_| ()),
+                (Stream::Starred, false) => dbconn.unstar(user.uid, id).await,
+                _ => Ok(()),
+            };
+            if let Err(e) = res {
+                return Err(server_error(e, user.uid));
+            }
+        }
+    }
+    Ok("OK")
+}
+
+pub(crate) async fn labels(dbconn: &DatabaseConnection, uid: u64) -> Result<Labels, DetailedError> {
+    let mut labels = vec![];
+    for channel in dbconn.get_channels_for_user(uid).await? {
+        if channel.query.is_some() {
+            continue;
+        }
+        let subs = dbconn.get_subbed(channel.cid).await?;
+        labels.push((channel, subs));
+    }
+    Ok(labels)
+}
+
+// stream_filters works out which posts are in the stream, along with the
+// xt= (exclude), it= (include), ot= (oldest), nt= (newest) and r=o (oldest first) options
+fn stream_filters(
+    id: &str,
+    params: &HashMap<String, String>,
+    labels: &Labels,
+) -> Result<StreamFilters, (StatusCode, String)> {
+    let mut filters = StreamFilters {
+        since: time_param(params, "ot")?,
+        until: time_param(params, "nt")?,
+        oldest_first: params.get("r").is_some_and(|x| x == "o"),
+        ..Default::default()
+    };
+    match parse_stream(id) {
+        Some(Stream::ReadingList) => {}
+        Some(Stream::Starred) => filters.starred = true,
+        Some(Stream::Read) => filters.read = Some(true),
+        Some(Stream::Label(name)) => match labels.iter().find(|(x, _)| x.name == name) {
+            Some((channel, _)) => filters.cid = Some(channel.cid),
+            None => return Err((StatusCode::NOT_FOUND, "No such label".to_string())),
+        },
+        Some(Stream::Feed(pid)) => filters.pid = Some(pid),
+        None => return Err((StatusCode::BAD_REQUEST, format!("Unknown stream {id}"))),
+    }
+    if params.get("xt").and_then(|x| parse_stream(x)) == Some(Stream::Read) {
+        filters.read = Some(false);
+    }
+    match params.get("it").and_then(|x| parse_stream(x)) {
+        Some(Stream::Read) => filters.read = Some(true),
+        Some(Stream::Starred) => filters.starred = true,
+        _ => {}
+    }
+    Ok(filters)
+}
+
+// item turns a post into a Reader API item, with the states and labels it has as categories
+fn item(post: &Post, labels: &Labels) -> Item {
+    let mut categories = vec![READING_LIST.to_string()];
+    if post.read == Some(true) {
+        categories.push(READ.to_string());
+    }
+    if post.starred == Some(true) {
+        categories.push(STARRED.to_string());
+    }
+    let mut html_url = None;
+    for (channel, subs) in labels {
+        if let Some(sub) = subs.iter().find(|x| x.pid == Some(post.pid)) {
+            categories.push(format!("{LABEL_PREFIX}{}", channel.name));
+            html_url = html_url.or(sub.site_url.clone());
+        }
+    }
+
+    let link = vec![Link {
+        href: post.link.clone(),
+    }];
+    Item {
+        id: item_id(post.id),
+        crawl_time_msec: post.date.timestamp_millis().to_string(),
+        timestamp_usec: post.date.timestamp_micros().to_string(),
+        published: post.date.timestamp(),
+        updated: post.updated.unwrap_or(post.date).timestamp(),
+        title: post.title.clone(),
+        author: post.author.clone(),
+        canonical: link.clone(),
+        alternate: link,
+        summary: Summary {
+            content: post.content.clone().unwrap_or(post.description.clone()),
+        },
+        categories,
+        origin: Origin {
+            stream_id: format!("feed/{}", post.pid),
+            title: post.publisher_name.clone().unwrap_or_default(),
+            html_url: html_url.unwrap_or_default(),
+        },
+    }
+}
+
+// item_ids reads every i= item id, answering 400 if one of them isn't an item id
+fn item_ids(params: &[(String, String)]) -> Result<Vec<u64>, (StatusCode, String)> {
+    params
+        .iter()
+        .filter(|(key, _)| key == "i")
+        .map(|(_, val)| match parse_item_id(val) {
+            Some(id) => Ok(id),
+            None => Err((StatusCode::BAD_REQUEST, format!("Invalid item id {val}"))),
+        })
+        .collect()
+}
+
+// continuation is where the next page starts, if there could be one
+fn continuation(len: usize, offset: u64, limit: u64) -> Option<String> {
+    (len as u64 == limit).then(|| (offset + limit).to_string())
+}
+
+fn number_param(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<u64>, (StatusCode, String)> {
+    match params.get(name).map(|x| x.parse::<u64>()) {
+        Some(Ok(val)) => Ok(Some(val)),
+        Some(Err(_)) => Err((StatusCode::BAD_REQUEST, format!("Invalid `{name}` field"))),
+        None => Ok(None),
+    }
+}
+
+// time_param reads a time given in seconds since the epoch
+fn time_param(
+    params: &HashMap<String, String>,
+    name: &str,
+) -> Result<Option<DateTime<Utc>>, (StatusCode, String)> {
+    match params.get(name).map(|x| x.parse::<i64>()) {
+        Some(Ok(val)) => match DateTime::from_timestamp(val, 0) {
+            Some(val) => Ok(Some(val)),
+            None => Err((StatusCode::BAD_REQUEST, format!("Invalid `{name}` field"))),
+        },
+        Some(Err(_)) => Err((StatusCode::BAD_REQUEST, format!("Invalid `{name}` field"))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod greader_tests {
+    use super::*;
+    use crate::rss_parser;
+    use serde_json::Value;
+
+    #[test]
+    fn test_parse_stream() {
+        assert_eq!(
+            parse_stream("user/-/state/com.google/reading-list"),
+            Some(Stream::ReadingList)
+        );
+        assert_eq!(
+            parse_stream("user/1234/state/com.google/starred"),
+            Some(Stream::Starred)
+        );
+        assert_eq!(
+            parse_stream("user/-/label/Tech news"),
+            Some(Stream::Label("Tech news".to_string()))
+        );
+        assert_eq!(parse_stream("feed/12"), Some(Stream::Feed(12)));
+        assert_eq!(parse_stream("feed/https://example.com/rss"), None);
+        assert_eq!(parse_stream("user/-/label/"), None);
+        assert_eq!(parse_stream("user/-/state/com.google/broadcast"), None);
+    }
+
+    #[test]
+    fn test_item_ids() {
+        assert_eq!(
+            item_id(31),
+            "tag:google.com,2005:reader/item/000000000000001f"
+        );
+        assert_eq!(parse_item_id(&item_id(31)), Some(31));
+        assert_eq!(parse_item_id("31"), Some(31));
+        assert_eq!(parse_item_id("-1"), Some(u64::MAX));
+        assert_eq!(parse_item_id("tag:google.com,2005:reader/item/xyz"), None);
+
+        let params = vec![
+            ("i".to_string(), "31".to_string()),
+            ("a".to_string(), READ.to_string()),
+            ("i".to_string(), item_id(32)),
+        ];
+        assert_eq!(item_ids(&params), Ok(vec![31, 32]));
+        let params = vec![("i".to_string(), "thirty".to_string())];
+        assert!(item_ids(&params).is_err());
+    }
+
+    #[test]
+    fn test_item() {
+        let mut post = Post::new_link("https://example.com/a".to_string());
+        post.id = 31;
+        post.pid = 2;
+        post.read = Some(true);
+        post.starred = Some(false);
+        post.publisher_name = Some("Example".to_string());
+        let labels = vec![(
+            Channel {
+                cid: 1,
+                name: "Tech".to_string(),
+                private: false,
+                query: None,
+            },
+            vec![Subscription {
+                cid: 1,
+                pid: Some(2),
+                site_url: Some("https://example.com".to_string()),
+                ..Default::default()
+            }],
+        )];
+        let item = match serde_json::to_value(item(&post, &labels)) {
+            Ok(val) => val,
+            Err(error) => panic!("{error}"),
+        };
+        assert_eq!(item["id"], item_id(31));
+        assert_eq!(item["summary"]["content"], "Test");
+        assert_eq!(item["origin"]["streamId"], "feed/2");
+        assert_eq!(item["origin"]["htmlUrl"], "https://example.com");
+        assert_eq!(
+            item["categories"],
+            serde_json::json!([READING_LIST, READ, "user/-/label/Tech"])
+        );
+    }
+
+    async fn get_json(client: &reqwest::Client, auth: &str, url: &str) -> Value {
+        let res = client
+            .get(url)
+            .header("Authorization", format!("GoogleLogin auth={auth}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200, "{url}");
+        serde_json::from_str(&res.text().await.unwrap()).unwrap()
+    }
+
+    async fn edit(client: &reqwest::Client, auth: &str, base: &str, form: &[(&str, &str)]) {
+        let res = client
+            .post(format!("{base}/reader/api/0/edit-tag"))
+            .header("Authorization", format!("GoogleLogin auth={auth}"))
+            .form(form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    // runs against the database at localhost:3306, see DatabaseConnection::new
+    #[ignore = "database"]
+    #[tokio::test]
+    async fn test_reader_api() {
+        let dbconn = DatabaseConnection::new();
+        let base = crate::serve_api(router(), dbconn.clone()).await;
+        let client = reqwest::Client::new();
+
+        let username = format!("reader_{}", &auth::new_token()[..10]);
+        let hash = auth::hash_password("correct horse").unwrap();
+        let uid = dbconn.create_user(&username, &hash).await.unwrap().unwrap();
+        let cid = dbconn
+            .insert_channel_for_user(uid, "Tech".to_string(), None)
+            .await
+            .unwrap();
+        dbconn
+            .subscribe(cid, format!("{base}/atom.xml"))
+            .await
+            .unwrap();
+        let feed = rss_parser::get_whole_feed(dbconn.get_subbed(cid).await.unwrap()).await;
+        dbconn.insert_posts(&feed.posts).await.unwrap();
+
+        let res = client
+            .post(format!("{base}/accounts/ClientLogin"))
+            .form(&[("Email", username.as_str()), ("Passwd", "battery staple")])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 401);
+        let res = client
+            .post(format!("{base}/accounts/ClientLogin"))
+            .form(&[("Email", username.as_str()), ("Passwd", "correct horse")])
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+        let body = res.text().await.unwrap();
+        let auth = body
+            .lines()
+            .find_map(|x| x.strip_prefix("Auth="))
+            .unwrap()
+            .to_string();
+
+        let res = client
+            .get(format!("{base}/reader/api/0/tag/list"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 401);
+        let tags = get_json(&client, &auth, &format!("{base}/reader/api/0/tag/list")).await;
+        assert_eq!(tags["tags"][1]["id"], "user/-/label/Tech");
+
+        let subs = get_json(
+            &client,
+            &auth,
+            &format!("{base}/reader/api/0/subscription/list?output=json"),
+        )
+        .await;
+        let sub = &subs["subscriptions"][0];
+        assert_eq!(sub["url"], format!("{base}/atom.xml"));
+        assert_eq!(sub["categories"][0]["label"], "Tech");
+
+        let ids = get_json(
+            &client,
+            &auth,
+            &format!("{base}/reader/api/0/stream/items/ids?s={READING_LIST}&xt={READ}"),
+        )
+        .await;
+        let ids = ids["itemRefs"].as_array().unwrap().clone();
+        assert_eq!(ids.len(), feed.posts.len());
+        let first = ids[0]["id"].as_str().unwrap();
+
+        edit(
+            &client,
+            &auth,
+            &base,
+            &[("i", first), ("a", READ), ("a", STARRED)],
+        )
+        .await;
+        let unread = get_json(
+            &client,
+            &auth,
+            &format!("{base}/reader/api/0/stream/contents/user/-/label/Tech?xt={READ}&n=100"),
+        )
+        .await;
+        assert_eq!(unread["items"].as_array().unwrap().len(), ids.len() - 1);
+
+        let starred = get_json(
+            &client,
+            &auth,
+            &format!("{base}/reader/api/0/stream/contents/{STARRED}"),
+        )
+        .await;
+        let item = &starred["items"][0];
+        assert_eq!(starred["items"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            parse_item_id(item["id"].as_str().unwrap()),
+            parse_item_id(first)
+        );
+        assert!(item["categories"]
+            .as_array()
+            .unwrap()
+            .contains(&Value::from(READ)));
+
+        edit(
+            &client,
+            &auth,
+            &base,
+            &[("i", first), ("r", READ), ("r", STARRED)],
+        )
+        .await;
+        let res = client
+            .post(format!("{base}/reader/api/0/stream/items/contents"))
+            .header("Authorization", format!("GoogleLogin auth={auth}"))
+            .form(&[("i", first)])
+            .send()
+            .await
+            .unwrap();
+        let contents: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(
+            contents["items"][0]["categories"],
+            serde_json::json!([READING_LIST, "user/-/label/Tech"])
+        );
+    }
+}
@@ -1,9 +1,19 @@
+use axum::http::StatusCode;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{event, Level};
 
+pub mod auth;
 pub mod database;
+pub mod feed_writer;
+pub mod fever;
+pub mod filter;
+pub mod greader;
 pub mod logger;
+pub mod opml;
 pub mod rss_parser;
+pub mod search;
 pub mod web_scraper;
 
 #[derive(Debug, Serialize)]
@@ -19,16 +29,99 @@ pub struct Post {
     enclosure: Option<String>,
     pid: u64,
     publisher_name: Option<String>,
+    // whoever the feed says wrote the post, if it says
+    author: Option<String>,
+    // the feed's own id for the post (rss guid, atom id)
+    #[serde(skip)]
+    guid: Option<String>,
+    // set once the publisher changes the post after we've stored it
+    updated: Option<DateTime<Utc>>,
+    // only sent when the revision history is asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revisions: Option<Vec<Revision>>,
+    // whether the user has read or starred the post, only sent when we know who's asking
+    #[serde(skip_serializing_if = "Option::is_none")]
+    read: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    starred: Option<bool>,
+    // the part of the post that matched a search, as html
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<String>,
+}
+
+// PostVersion is a previous version of a post as stored in the post_revision table
+pub struct PostVersion {
+    pub title: String,
+    pub description: String,
+    pub content: Option<String>,
+    // when this version was replaced
+    pub revised_at: DateTime<Utc>,
+}
+
+// Revision lists what the publisher changed at a point in time
+#[derive(Debug, Serialize)]
+pub struct Revision {
+    revised_at: DateTime<Utc>,
+    changes: Vec<Change>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Change {
+    field: &'static str,
+    old: Option<String>,
+    new: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct Channel {
     cid: u64,
     name: String,
+    // whether reading the channel's published feed needs a token.
+    // Only a hash of the token is stored, it's shown once when it's made
+    private: bool,
+    // smart channels are made of the posts matching a query instead of subscriptions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<SmartQuery>,
+}
+
+/// SmartQuery picks the posts for a smart channel out of everything the user is subscribed to.
+/// Everything that's set has to match.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SmartQuery {
+    // a search, see search::SearchQuery
+    pub keywords: Option<String>,
+    // only posts from these publishers, any publisher if empty
+    pub pids: Vec<u64>,
+    // only posts from the last so many days
+    pub days: Option<u32>,
+    pub starred: bool,
+    pub unread: bool,
+}
+
+impl SmartQuery {
+    /// validate checks the query can be run, returning what's wrong with it if not
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(keywords) = &self.keywords {
+            if search::SearchQuery::parse(keywords).is_empty() {
+                return Err("The keywords need something to look for".to_string());
+            }
+        }
+        if self.days == Some(0) {
+            return Err("The number of days has to be more than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Channel {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 // Sub requires a post body that deserializes into the Subscription struct
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Default, Clone)]
 pub struct Subscription {
     pub cid: u64,
     pub pid: Option<u64>,
@@ -36,6 +129,92 @@ pub struct Subscription {
     pub url: String,
     #[serde(default)]
     pub name: String,
+    // the publisher's website, as opposed to its feed
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub site_url: Option<String>,
+    // cache validators from the last fetch, sent back so unchanged feeds answer 304
+    #[serde(skip)]
+    pub etag: Option<String>,
+    #[serde(skip)]
+    pub last_modified: Option<String>,
+    // how fetching the publisher's feed has been going
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub status: Option<FeedStatus>,
+    // the subscriber's own retention policy for the publisher, if it overrides the default
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionPolicy>,
+}
+
+// a publisher is disabled after failing this many fetches in a row
+pub const MAX_CONSECUTIVE_FAILURES: u32 = 10;
+// how often update_feed_task fetches feeds, in minutes
+pub const FETCH_INTERVAL_MINUTES: i64 = 30;
+// the longest we'll back off for, in minutes
+pub const MAX_BACKOFF_MINUTES: i64 = 24 * 60;
+
+/// RetentionPolicy is how long posts are kept before the prune job deletes them.
+/// A post is deleted once it's older than `days` or no longer one of its publisher's latest `posts`.
+/// Starred posts are always kept.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RetentionPolicy {
+    pub days: Option<u32>,
+    pub posts: Option<u32>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            days: Some(90),
+            posts: None,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// from_env reads the global policy from RSS_RETENTION_DAYS and RSS_RETENTION_POSTS.
+    /// Setting either to 0 turns that limit off, and anything unset or invalid uses the default.
+    pub fn from_env() -> Self {
+        fn var(name: &str) -> Option<u32> {
+            std::env::var(name).ok().and_then(|x| x.parse::<u32>().ok())
+        }
+        let default = RetentionPolicy::default();
+        RetentionPolicy {
+            days: var("RSS_RETENTION_DAYS")
+                .map(|x| Some(x).filter(|x| *x > 0))
+                .unwrap_or(default.days),
+            posts: var("RSS_RETENTION_POSTS")
+                .map(|x| Some(x).filter(|x| *x > 0))
+                .unwrap_or(default.posts),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct FeedStatus {
+    pub last_fetched: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub consecutive_failures: u32,
+    pub last_status: Option<u16>,
+    pub last_error: Option<String>,
+    pub disabled: bool,
+}
+
+impl FeedStatus {
+    /// is_due checks if the publisher should be fetched again.
+    /// Failing publishers are backed off exponentially, and disabled ones are never fetched.
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        if self.disabled {
+            return false;
+        }
+        let last_fetched = match self.last_fetched {
+            Some(val) if self.consecutive_failures > 0 => val,
+            _ => return true,
+        };
+        let exponent = (self.consecutive_failures - 1).min(16);
+        let wait = (FETCH_INTERVAL_MINUTES << exponent).min(MAX_BACKOFF_MINUTES);
+        // the task runs on a schedule, so allow a little slack for when the last fetch finished
+        now - last_fetched >= chrono::Duration::minutes(wait - 5)
+    }
 }
 
 impl Post {
@@ -53,13 +232,91 @@ impl Post {
             enclosure: None,
             pid: 10000,
             publisher_name: None,
+            author: None,
+            guid: None,
+            updated: None,
+            revisions: None,
+            read: None,
+            starred: None,
+            snippet: None,
         }
     }
 
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+
     pub fn set_content(&mut self, new_content: String) {
         self.content = Some(new_content);
     }
 
+    /// identity is what we use to tell posts from the same publisher apart.
+    /// It's the feed's guid if there is one, otherwise the link without any tracking parameters.
+    pub fn identity(&self) -> String {
+        match &self.guid {
+            Some(guid) => guid.to_string(),
+            None => rss_parser::normalize_url(&self.link),
+        }
+    }
+
+    /// content_hash is used to tell if the publisher has changed the post since we stored it
+    pub fn content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.title);
+        hasher.update([0]);
+        hasher.update(&self.description);
+        hasher.update([0]);
+        hasher.update(self.content.as_deref().unwrap_or(""));
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// set_revisions works out what changed between each stored version of the post.
+    /// versions are expected oldest first, the last one is compared against the current post
+    pub fn set_revisions(&mut self, versions: Vec<PostVersion>) {
+        let mut revisions = Vec::new();
+        for (i, old) in versions.iter().enumerate() {
+            let (title, description, content) = match versions.get(i + 1) {
+                Some(next) => (&next.title, &next.description, &next.content),
+                None => (&self.title, &self.description, &self.content),
+            };
+            let mut changes = Vec::new();
+            if &old.title != title {
+                changes.push(Change {
+                    field: "title",
+                    old: Some(old.title.to_string()),
+                    new: Some(title.to_string()),
+                });
+            }
+            if &old.description != description {
+                changes.push(Change {
+                    field: "description",
+                    old: Some(old.description.to_string()),
+                    new: Some(description.to_string()),
+                });
+            }
+            if &old.content != content {
+                changes.push(Change {
+                    field: "content",
+                    old: old.content.clone(),
+                    new: content.clone(),
+                });
+            }
+            revisions.push(Revision {
+                revised_at: old.revised_at,
+                changes,
+            });
+        }
+        self.revisions = Some(revisions);
+    }
+
+    pub fn content(&self) -> Option<&str> {
+        self.content.as_deref()
+    }
+
     pub fn get_content(&self) -> String {
         let str_content = &self.content;
         let str_content = str_content.to_owned();
@@ -67,11 +324,130 @@ impl Post {
     }
 }
 
+/// escape_xml makes text safe to put in an xml document, either as an attribute or between tags
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// server_error logs an error the reader apis hit and turns it into a 500 for the client
+pub(crate) fn server_error(e: logger::DetailedError, uid: u64) -> (StatusCode, String) {
+    event!(
+        Level::ERROR,
+        backtrace = ?e,
+        description = e.desc,
+        uid
+    );
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "We had an issue with the request..".to_string(),
+    )
+}
+
+/// serve_api runs a reader api for its tests, along with a feed to subscribe to at /atom.xml
+#[cfg(test)]
+pub(crate) async fn serve_api(
+    api: axum::Router<database::DatabaseConnection>,
+    dbconn: database::DatabaseConnection,
+) -> String {
+    let app = api.route(
+        "/atom.xml",
+        axum::routing::get(|| async { std::fs::read_to_string("test-files/atom.xml").unwrap() }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app.with_state(dbconn)).await.unwrap() });
+    format!("http://{addr}")
+}
+
 #[cfg(test)]
 mod integrated_tests {
     use super::*;
     use database::DatabaseConnection;
 
+    #[test]
+    fn test_revision_changes() {
+        let mut post = Post::new_link("https://example.com/a".to_string());
+        let first = PostVersion {
+            title: "Tset".to_string(),
+            description: "Old".to_string(),
+            content: None,
+            revised_at: Utc::now(),
+        };
+        let second = PostVersion {
+            title: "Test".to_string(),
+            description: "Old".to_string(),
+            content: None,
+            revised_at: Utc::now(),
+        };
+        let hash = post.content_hash();
+        post.set_revisions(vec![first, second]);
+        let revisions = post.revisions.unwrap();
+        // first version had the typo in the title, second had a different description
+        assert_eq!(revisions[0].changes.len(), 1);
+        assert_eq!(revisions[0].changes[0].field, "title");
+        assert_eq!(revisions[1].changes.len(), 1);
+        assert_eq!(revisions[1].changes[0].field, "description");
+        assert_eq!(revisions[1].changes[0].new, Some("Test".to_string()));
+
+        let mut changed = Post::new_link("https://example.com/a".to_string());
+        changed.set_content("new".to_string());
+        assert_ne!(hash, changed.content_hash());
+    }
+
+    #[test]
+    fn test_feed_backoff() {
+        let now = Utc::now();
+        let mut status = FeedStatus {
+            last_fetched: Some(now - chrono::Duration::minutes(30)),
+            ..Default::default()
+        };
+        assert!(status.is_due(now));
+
+        // one failure waits for the next run, three failures wait 2 hours
+        status.consecutive_failures = 1;
+        assert!(status.is_due(now));
+        status.consecutive_failures = 3;
+        assert!(!status.is_due(now));
+        status.last_fetched = Some(now - chrono::Duration::hours(2));
+        assert!(status.is_due(now));
+
+        // never more than a day
+        status.consecutive_failures = 9;
+        assert!(!status.is_due(now));
+        status.last_fetched = Some(now - chrono::Duration::days(1));
+        assert!(status.is_due(now));
+
+        status.disabled = true;
+        assert!(!status.is_due(now));
+    }
+
+    #[test]
+    fn test_smart_query() {
+        // anything left out of the json is unset
+        let query: SmartQuery = match serde_json::from_str(r#"{"keywords": "rust -crab"}"#) {
+            Ok(val) => val,
+            Err(error) => panic!("{error}"),
+        };
+        assert!(query.pids.is_empty());
+        assert!(!query.starred);
+        assert!(query.validate().is_ok());
+
+        let only_excluded = SmartQuery {
+            keywords: Some("-crab".to_string()),
+            ..Default::default()
+        };
+        assert!(only_excluded.validate().is_err());
+        let no_days = SmartQuery {
+            days: Some(0),
+            ..Default::default()
+        };
+        assert!(no_days.validate().is_err());
+    }
+
     #[test]
     fn macro_test() {
         // let params = log_params!("unit", 42);
@@ -85,10 +461,11 @@ mod integrated_tests {
                 .to_string(),
             pid: Some(5),
             cid: 1,
-            name: "nil".to_string()
+            name: "nil".to_string(),
+            ..Default::default()
         };
         let urls: Vec<Subscription> = vec![obj];
-        let posts = rss_parser::get_whole_feed(urls).await;
+        let posts = rss_parser::get_whole_feed(urls).await.posts;
         assert!(posts.len() > 0);
 
         let conn = DatabaseConnection::new();
@@ -1,34 +1,67 @@
 use rss_api::{
-    database::DatabaseConnection,
+    auth::{self, ApiToken, AuthUser, Scope},
+    database::{DatabaseConnection, ImportResult, SubscribeOutcome},
+    feed_writer::{self, FeedMeta},
+    fever,
+    filter::FilterRule,
+    greader,
     logger::{self, DetailedError},
-    rss_parser, web_scraper, Channel, Post, Subscription,
+    rss_parser,
+    search::{self, SearchFilters, SearchQuery},
+    web_scraper, Channel, Post, RetentionPolicy, SmartQuery, Subscription,
 };
 
 use axum::{
     debug_handler,
-    extract::{Json, Query, State},
+    extract::{FromRef, Json, Path, Query, State},
     http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::{get, post},
     Router,
 };
+use axum_extra::extract::cookie::CookieJar;
 use axum_server::tls_rustls::RustlsConfig;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::stream::{self, Stream};
 use http::{
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-    HeaderValue, Method,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_TYPE, HOST},
+    HeaderMap, HeaderValue, Method,
 };
-use serde::Deserialize;
-use std::collections::HashMap;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::env;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
 use tokio_cron_scheduler::{Job, JobScheduler};
 use tower::ServiceBuilder;
-use tower_http::{cors::CorsLayer, services::ServeFile, trace::TraceLayer};
+use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing::{event, Level};
 use tracing_subscriber::{filter, layer::Layer, prelude::*};
 
-#[derive(Clone)]
+// default and largest number of posts /feed returns at a time
+const FEED_PAGE_SIZE: u64 = 50;
+const MAX_FEED_PAGE_SIZE: u64 = 200;
+// how many of the latest posts a filter preview checks
+const FILTER_PREVIEW_POSTS: u64 = 200;
+// how many ingests /events can fall behind before it has to catch up from the database
+const EVENTS_CAPACITY: usize = 16;
+// how many posts /events reads from the database at a time
+const EVENTS_BATCH_SIZE: u64 = 50;
+// how long /feed?refresh=true waits before fetching the same channel's feeds again
+const REFRESH_COOLDOWN_MINUTES: i64 = 5;
+
+#[derive(Clone, FromRef)]
 struct Appstate {
     dbconn: DatabaseConnection,
+    // the id of the newest post each ingest stored, for /events to pick up
+    events: broadcast::Sender<u64>,
+    // when each channel was last refreshed from /feed, so refreshes can't be spammed
+    refreshed: Arc<Mutex<HashMap<u64, DateTime<Utc>>>>,
 }
 
 #[tokio::main]
@@ -39,6 +72,7 @@ async fn main() {
         )
         .init();
     let dbconn = DatabaseConnection::new();
+    let (events, _) = broadcast::channel(EVENTS_CAPACITY);
 
     let origin = match env::var("IS_DOCKER_COMPOSED") {
         Ok(val) => val,
@@ -54,16 +88,50 @@ async fn main() {
         .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
 
     let app = Router::new()
+        .route("/register", post(register))
+        .route("/login", post(login))
+        .route("/logout", post(logout))
+        .route("/me", get(me))
+        .route(
+            "/token",
+            get(get_api_tokens)
+                .post(create_api_token)
+                .delete(delete_api_token),
+        )
         .route("/all", get(all_posts))
         .route("/feed", get(feed))
+        .route("/events", get(post_events))
         .route("/sub", get(get_subs).post(sub).delete(unsub))
+        .route("/sub/enable", post(enable_sub))
+        .route("/sub/retention", post(set_retention))
         .route("/read", post(read))
+        .route("/read/mark", post(mark_read))
+        .route("/star", post(star).delete(unstar))
+        .route("/starred", get(starred))
+        .route("/search", get(search_posts))
+        .route("/import/opml", post(import_opml))
+        .route("/export/opml", get(export_opml))
         .route(
             "/channel",
             get(get_channels).post(post_channel).delete(delete_channel),
         )
-        .route_service("/logs", ServeFile::new("error_log.xml"))
-        .with_state(Appstate { dbconn })
+        .route(
+            "/channel/token",
+            post(create_feed_token).delete(delete_feed_token),
+        )
+        .route("/channel/:cid/:file", get(channel_feed))
+        .route(
+            "/filter",
+            get(get_filters).post(post_filter).delete(delete_filter),
+        )
+        .route("/filter/preview", post(preview_filter))
+        .merge(greader::router())
+        .merge(fever::router())
+        .with_state(Appstate {
+            dbconn,
+            events: events.clone(),
+            refreshed: Arc::new(Mutex::new(HashMap::new())),
+        })
         .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()))
         .layer(cors);
 
@@ -71,9 +139,23 @@ async fn main() {
     sched
         .add(
             // every 30 mins
-            Job::new_async("0 0,30 * * * *", |_, _| {
+            Job::new_async("0 0,30 * * * *", move |_, _| {
+                let events = events.clone();
+                Box::pin(async move {
+                    update_feed_task(&events).await;
+                })
+            })
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    sched
+        .add(
+            // every day at 3:15
+            Job::new_async("0 15 3 * * *", |_, _| {
                 Box::pin(async {
-                    update_feed_task().await;
+                    prune_posts_task().await;
                 })
             })
             .unwrap(),
@@ -103,16 +185,323 @@ async fn main() {
     };
 }
 
+#[derive(Deserialize)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct Me {
+    uid: u64,
+    username: String,
+}
+
+// register makes an account along with its first channel, and logs straight into it
+async fn register(
+    State(state): State<Appstate>,
+    jar: CookieJar,
+    Json(payload): Json<Credentials>,
+) -> Result<(CookieJar, Json<Me>), (StatusCode, String)> {
+    if let Err(reason) = auth::check_credentials(&payload.username, &payload.password) {
+        return Err((StatusCode::BAD_REQUEST, reason));
+    }
+    let hash = match auth::hash_password(&payload.password) {
+        Ok(val) => val,
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+            );
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ));
+        }
+    };
+
+    let res = match state.dbconn.create_user(&payload.username, &hash).await {
+        Ok(Some(uid)) => state
+            .dbconn
+            .insert_channel_for_user(uid, "Main feed".to_string(), None)
+            .await
+            .map(|_| Some(uid)),
+        other => other,
+    };
+    let uid = match res {
+        Ok(Some(uid)) => uid,
+        Ok(None) => return Err((StatusCode::CONFLICT, "That username is taken".to_string())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+            );
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ));
+        }
+    };
+
+    let jar = start_session(&state, jar, uid).await?;
+    Ok((
+        jar,
+        Json(Me {
+            uid,
+            username: payload.username,
+        }),
+    ))
+}
+
+async fn login(
+    State(state): State<Appstate>,
+    jar: CookieJar,
+    Json(payload): Json<Credentials>,
+) -> Result<(CookieJar, Json<Me>), (StatusCode, String)> {
+    let login = state.dbconn.get_login(&payload.username).await;
+    let uid = match login.map(|x| auth::check_login(x, &payload.password)) {
+        Ok(Some(uid)) => uid,
+        // the same answer either way, so usernames can't be guessed
+        Ok(None) => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                "Wrong username or password".to_string(),
+            ))
+        }
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+            );
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ));
+        }
+    };
+
+    let jar = start_session(&state, jar, uid).await?;
+    Ok((
+        jar,
+        Json(Me {
+            uid,
+            username: payload.username,
+        }),
+    ))
+}
+
+// start_session logs the user in, handing back the session cookie
+async fn start_session(
+    state: &Appstate,
+    jar: CookieJar,
+    uid: u64,
+) -> Result<CookieJar, (StatusCode, String)> {
+    let token = auth::new_token();
+    let expires_at = Utc::now() + Duration::days(auth::SESSION_DAYS);
+    match state
+        .dbconn
+        .create_session(uid, &auth::hash_token(&token), expires_at)
+        .await
+    {
+        Ok(()) => Ok(jar.add(auth::session_cookie(token))),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+async fn logout(
+    State(state): State<Appstate>,
+    jar: CookieJar,
+) -> Result<CookieJar, (StatusCode, String)> {
+    let token = match jar.get(auth::SESSION_COOKIE) {
+        Some(cookie) => auth::hash_token(cookie.value()),
+        None => return Ok(jar),
+    };
+    match state.dbconn.delete_session(&token).await {
+        Ok(()) => Ok(jar.remove(auth::removal_cookie())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+// me tells the frontend who's logged in
+async fn me(
+    State(state): State<Appstate>,
+    user: AuthUser,
+) -> Result<Json<Me>, (StatusCode, String)> {
+    match state.dbconn.get_username(user.uid).await {
+        Ok(Some(username)) => Ok(Json(Me {
+            uid: user.uid,
+            username,
+        })),
+        Ok(None) => Err((StatusCode::UNAUTHORIZED, "Not logged in".to_string())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid = user.uid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+async fn get_api_tokens(
+    State(state): State<Appstate>,
+    user: AuthUser,
+) -> Result<Json<Vec<ApiToken>>, (StatusCode, String)> {
+    match state.dbconn.get_api_tokens(user.uid).await {
+        Ok(tokens) => Ok(Json(tokens)),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid = user.uid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NewApiToken {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Serialize)]
+struct CreatedApiToken {
+    // only ever shown here, we just keep its hash
+    token: String,
+    #[serde(flatten)]
+    info: ApiToken,
+}
+
+// create_api_token makes a token for scripts and other clients to send as `Authorization: Bearer`
+async fn create_api_token(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Json(mut payload): Json<NewApiToken>,
+) -> Result<Json<CreatedApiToken>, (StatusCode, String)> {
+    let name = payload.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > 100 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The name has to be between 1 and 100 characters".to_string(),
+        ));
+    }
+    payload.scopes.sort();
+    payload.scopes.dedup();
+    if payload.scopes.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The token needs at least one scope".to_string(),
+        ));
+    }
+
+    let token = auth::new_api_token();
+    match state
+        .dbconn
+        .create_api_token(user.uid, &name, &payload.scopes, &auth::hash_token(&token))
+        .await
+    {
+        Ok(tid) => Ok(Json(CreatedApiToken {
+            token,
+            info: ApiToken {
+                tid,
+                name,
+                scopes: payload.scopes,
+                created_at: Utc::now(),
+                last_used_at: None,
+            },
+        })),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid = user.uid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+// delete_api_token revokes a token, it stops working straight away
+async fn delete_api_token(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<(), (StatusCode, String)> {
+    let tid = match params.get("tid").map(|x| x.parse::<u64>()) {
+        Some(Ok(val)) => val,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Missing required `tid` field".to_string(),
+            ))
+        }
+    };
+
+    match state.dbconn.delete_api_token(user.uid, tid).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "No such token".to_string())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid = user.uid,
+                tid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
 #[debug_handler]
 async fn all_posts(
     Query(params): Query<HashMap<String, String>>,
     State(state): State<Appstate>,
+    user: AuthUser,
 ) -> Result<Json<Vec<Post>>, (StatusCode, String)> {
-    let uid = params.get("uid").map(|x| x.parse::<u64>());
-    let uid = match uid {
-        Some(Ok(val)) => val,
-        _ => return Err((StatusCode::BAD_REQUEST, "Invalid id passed!".to_string())),
-    };
+    let uid = user.uid;
     let offset = params.get("offset").map(|x| x.parse::<u64>());
     let offset = match offset {
         Some(Ok(val)) => val,
@@ -146,13 +535,16 @@ async fn all_posts(
 
 #[derive(Deserialize)]
 struct ReadQuery {
-    id: Option<u64>,
-    url: String,
+    id: u64,
     scrape: bool,
+    // include what the publisher changed since the post was first stored
+    #[serde(default)]
+    revisions: bool,
 }
 #[debug_handler]
 async fn read(
     State(state): State<Appstate>,
+    user: AuthUser,
     Json(payload): Json<ReadQuery>,
 ) -> Result<Json<Post>, (StatusCode, String)> {
     let to_scrape = payload.scrape;
@@ -164,33 +556,72 @@ async fn read(
     // To terminate the lifeline early and hence not cause this error, we execute get_post in its own scope and error handle there
     // At the end of the scope, `res` gets dropped and we can use the other await.
     let mut post = {
-        let res = state
-            .dbconn
-            .get_post(payload.id, Some(payload.url.to_string()))
-            .await;
+        let res = state.dbconn.get_post(user.uid, payload.id).await;
 
         match res {
-            Ok(val) => val,
+            Ok(Some(val)) => val,
+            Ok(None) => return Err((StatusCode::NOT_FOUND, "No such post".to_string())),
             Err(e) => {
                 event!(
                     Level::ERROR,
                     backtrace = ?e,
                     description = e.desc,
-                    url = ?payload.url,
-                    id = ?payload.id
+                    id = payload.id
                 );
                 return Err((
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    "Error resource doesn't exist".to_string(),
+                    "We had some issues with the request...".to_string(),
                 ));
             }
         }
     };
 
+    // not being able to mark it shouldn't stop the post from being read
+    let res = match state
+        .dbconn
+        .mark_read(user.uid, Some(post.id()), None, None, None)
+        .await
+    {
+        Ok(()) => state.dbconn.load_post_state(user.uid, &mut post).await,
+        Err(e) => Err(e),
+    };
+    let snapshot = match res {
+        Ok(val) => val,
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                id = payload.id,
+            );
+            None
+        }
+    };
+
+    if payload.revisions {
+        if let Err(e) = state.dbconn.load_revisions(&mut post).await {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                id = payload.id,
+            );
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had some issues with the request...".to_string(),
+            ));
+        }
+    }
+
     if to_scrape {
         let res = web_scraper::scrape(&mut post).await;
         match res {
             Ok(_) => Ok(Json(post)),
+            // the article may be gone, but starred posts kept a copy
+            Err(_) if snapshot.is_some() => {
+                post.set_content(snapshot.unwrap());
+                Ok(Json(post))
+            }
             Err(e) => {
                 event!(
                     Level::ERROR,
@@ -209,179 +640,965 @@ async fn read(
     }
 }
 
-async fn sub(
+// search_posts takes the search in `q`, see SearchQuery for what it can do.
+// It can be narrowed down with `pid`, `cid`, and a `from` and `to` date.
+async fn search_posts(
     State(state): State<Appstate>,
-    Json(payload): Json<Subscription>,
-) -> Result<(), (StatusCode, String)> {
-    let res = state
+    user: AuthUser,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<Post>>, (StatusCode, String)> {
+    let uid = user.uid;
+    let query = SearchQuery::parse(params.get("q").map(|x| x.as_str()).unwrap_or_default());
+    if query.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Nothing to search for!".to_string(),
+        ));
+    }
+
+    let id = |name: &str| match params.get(name).map(|x| x.parse::<u64>()) {
+        None => Ok(None),
+        Some(Ok(val)) => Ok(Some(val)),
+        Some(Err(_)) => Err((StatusCode::BAD_REQUEST, format!("Invalid {name} passed!"))),
+    };
+    let date = |name: &str, end| match params.get(name).map(|x| search::parse_date(x, end)) {
+        None => Ok(None),
+        Some(Some(val)) => Ok(Some(val)),
+        Some(None) => Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid {name} date passed!"),
+        )),
+    };
+    let filters = SearchFilters {
+        pid: id("pid")?,
+        cid: id("cid")?,
+        from: date("from", false)?,
+        to: date("to", true)?,
+    };
+    let offset = id("offset")?.unwrap_or(0);
+    let limit = id("limit")?
+        .map(|x| x.min(MAX_FEED_PAGE_SIZE))
+        .unwrap_or(FEED_PAGE_SIZE);
+
+    match state
         .dbconn
-        .subscribe(payload.cid, payload.url.to_string())
-        .await;
-    match res {
-        Ok(()) => Ok(()),
+        .search(uid, &query, &filters, offset, limit)
+        .await
+    {
+        Ok(val) => Ok(Json(val)),
         Err(e) => {
             event!(
                 Level::ERROR,
                 backtrace = ?e,
                 description = e.desc,
-                url = ?payload.url,
+                uid,
             );
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
-                e.friendly_desc.unwrap_or("".to_string()) + &e.desc,
+                "We had some issues with the request...".to_string(),
             ))
         }
     }
 }
 
-async fn unsub(
+#[derive(Deserialize)]
+struct StarPost {
+    id: u64,
+}
+
+// star keeps a post for the user, snapshotting its article so it stays readable if the original goes away
+async fn star(
     State(state): State<Appstate>,
-    Json(payload): Json<Subscription>,
+    user: AuthUser,
+    Json(payload): Json<StarPost>,
 ) -> Result<(), (StatusCode, String)> {
-    let res = state
-        .dbconn
-        .unsubscribe(payload.pid.unwrap_or(0), payload.cid)
-        .await;
-    match res {
-        Ok(()) => Ok(()),
+    // checked before scraping so other users' posts are never fetched
+    let mut post = match state.dbconn.get_post(user.uid, payload.id).await {
+        Ok(Some(val)) => val,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "No such post".to_string())),
         Err(e) => {
             event!(
                 Level::ERROR,
                 backtrace = ?e,
-                description = e.to_string(),
+                description = e.desc,
+                id = payload.id,
+            );
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had some issues with the request...".to_string(),
+            ));
+        }
+    };
+
+    // scraped in its own scope so the error is dropped before the next await, see read
+    let snapshot = {
+        let res = web_scraper::scrape(&mut post).await;
+        match res {
+            Ok(_) => Some(post.get_content()),
+            Err(e) => {
+                // still worth starring, the feed's own content is better than nothing
+                event!(
+                    Level::ERROR,
+                    backtrace = ?e,
+                    description = e.to_string(),
+                    url = ?post.link,
+                );
+                None
+            }
+        }
+    };
+    let snapshot = snapshot.or(post.content().map(|x| x.to_string()));
+
+    let res = match state.dbconn.star(user.uid, payload.id, snapshot).await {
+        Ok(0) => state.dbconn.subscribes_to(user.uid, post.pid()).await,
+        Ok(_) => Ok(true),
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok(true) => Ok(()),
+        // starred posts stay readable after unsubscribing, but nothing new can be starred from them
+        Ok(false) => Err((StatusCode::NOT_FOUND, "No such post".to_string())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                id = payload.id,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had some issues with the request...".to_string(),
+            ))
+        }
+    }
+}
+
+async fn unstar(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<(), (StatusCode, String)> {
+    let uid = user.uid;
+    let id = match params.get("id").map(|x| x.parse::<u64>()) {
+        Some(Ok(val)) => val,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Missing required `id` field".to_string(),
+            ))
+        }
+    };
+
+    match state.dbconn.unstar(uid, id).await {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                id,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had some issues with the request...".to_string(),
+            ))
+        }
+    }
+}
+
+async fn starred(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<Post>>, (StatusCode, String)> {
+    let uid = user.uid;
+    let offset = match params.get("offset").map(|x| x.parse::<u64>()) {
+        None => 0,
+        Some(Ok(val)) => val,
+        Some(Err(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Invalid offset passed!".to_string(),
+            ))
+        }
+    };
+    let limit = match params.get("limit").map(|x| x.parse::<u64>()) {
+        None => FEED_PAGE_SIZE,
+        Some(Ok(val)) => val.min(MAX_FEED_PAGE_SIZE),
+        Some(Err(_)) => return Err((StatusCode::BAD_REQUEST, "Invalid limit passed!".to_string())),
+    };
+
+    match state.dbconn.get_starred(uid, offset, limit).await {
+        Ok(val) => Ok(Json(val)),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid,
+                offset
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had some issues with the request...".to_string(),
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MarkRead {
+    // narrow down what gets marked, leaving all of them out marks everything
+    id: Option<u64>,
+    cid: Option<u64>,
+    before: Option<DateTime<Utc>>,
+}
+
+// mark_read marks a post, a channel, or everything older than a time as read
+async fn mark_read(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Json(payload): Json<MarkRead>,
+) -> Result<(), (StatusCode, String)> {
+    let res = state
+        .dbconn
+        .mark_read(user.uid, payload.id, payload.cid, None, payload.before)
+        .await;
+    match res {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid = user.uid,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had some issues with the request...".to_string(),
+            ))
+        }
+    }
+}
+
+// sub answers 300 Multiple Choices with the feeds found when the url has more than one
+async fn sub(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Json(payload): Json<Subscription>,
+) -> Result<Response, (StatusCode, String)> {
+    check_channel(&state, &user, payload.cid).await?;
+    let res = state
+        .dbconn
+        .subscribe(payload.cid, payload.url.to_string())
+        .await;
+    match res {
+        Ok(SubscribeOutcome::Subscribed) => Ok(StatusCode::OK.into_response()),
+        Ok(SubscribeOutcome::Candidates(found)) => {
+            Ok((StatusCode::MULTIPLE_CHOICES, Json(found)).into_response())
+        }
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                url = ?payload.url,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                e.friendly_desc.unwrap_or("".to_string()) + &e.desc,
+            ))
+        }
+    }
+}
+
+async fn unsub(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Json(payload): Json<Subscription>,
+) -> Result<(), (StatusCode, String)> {
+    check_channel(&state, &user, payload.cid).await?;
+    let res = state
+        .dbconn
+        .unsubscribe(payload.pid.unwrap_or(0), payload.cid)
+        .await;
+    match res {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.to_string(),
+                id = payload.pid,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had some issues with the request...".to_string(),
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EnablePublisher {
+    pid: u64,
+}
+// enable_sub starts fetching a publisher that was disabled after failing too many times
+async fn enable_sub(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Json(payload): Json<EnablePublisher>,
+) -> Result<(), (StatusCode, String)> {
+    check_publisher(&state, &user, payload.pid).await?;
+    match state.dbconn.enable_publisher(payload.pid).await {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.to_string(),
                 id = payload.pid,
             );
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "We had some issues with the request...".to_string(),
+                "We had some issues with the request...".to_string(),
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PublisherRetention {
+    pid: u64,
+    #[serde(flatten)]
+    policy: RetentionPolicy,
+}
+// set_retention overrides how long a publisher's posts are kept, leave a limit out to use the default
+async fn set_retention(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Json(payload): Json<PublisherRetention>,
+) -> Result<(), (StatusCode, String)> {
+    match state
+        .dbconn
+        .set_retention(user.uid, payload.pid, &payload.policy)
+        .await
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "No such subscription".to_string())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                id = payload.pid,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had some issues with the request...".to_string(),
+            ))
+        }
+    }
+}
+
+#[debug_handler]
+async fn get_subs(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<Subscription>>, (StatusCode, String)> {
+    let res = params.get("cid").map(|x| x.parse::<u64>());
+    match res {
+        Some(Ok(cid)) => {
+            check_channel(&state, &user, cid).await?;
+            let urls = state.dbconn.get_subbed(cid).await;
+
+            match urls {
+                Ok(vecs) => Ok(Json(vecs)),
+                Err(e) => {
+                    event!(
+                        Level::ERROR,
+                        backtrace = ?e,
+                        description = e.to_string(),
+                    );
+                    Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "We had some issues with the request...".to_string(),
+                    ))
+                }
+            }
+        }
+        Some(Err(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Invalid ID value passed".to_string(),
+            ))
+        }
+        None => {
+            let urls = state.dbconn.get_subbed_for_user(user.uid).await;
+
+            match urls {
+                Ok(vecs) => Ok(Json(vecs)),
+                Err(e) => {
+                    event!(
+                        Level::ERROR,
+                        backtrace = ?e,
+                        description = e.to_string(),
+                        uid = user.uid,
+                    );
+                    Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "We had some issues with the request...".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+// Feed gets the front page aggregated posts for a channel, newest first.
+// Posts are read from the database, which update_feed_task keeps fresh.
+// EXPECTED QUERY PARAMS: cid, OPTIONAL: offset, limit, refresh
+#[debug_handler]
+async fn feed(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<Post>>, (StatusCode, String)> {
+    let res = params.get("cid").map(|x| x.parse::<u64>());
+    let cid = match res {
+        Some(Ok(val)) => val,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Invalid ID value passed".to_string(),
+            ))
+        }
+    };
+    let offset = match params.get("offset").map(|x| x.parse::<u64>()) {
+        None => 0,
+        Some(Ok(val)) => val,
+        Some(Err(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Invalid offset passed!".to_string(),
+            ))
+        }
+    };
+    let limit = match params.get("limit").map(|x| x.parse::<u64>()) {
+        None => FEED_PAGE_SIZE,
+        Some(Ok(val)) => val.min(MAX_FEED_PAGE_SIZE),
+        Some(Err(_)) => return Err((StatusCode::BAD_REQUEST, "Invalid limit passed!".to_string())),
+    };
+
+    check_channel(&state, &user, cid).await?;
+
+    // fetch the channel's feeds in the background, the new posts show up on the next load
+    if params.get("refresh").is_some_and(|x| x == "true") && start_refresh(&state, cid) {
+        let dbconn = state.dbconn.clone();
+        let events = state.events.clone();
+        tokio::spawn(async move {
+            match dbconn.get_subbed(cid).await {
+                Ok(subs) => {
+                    // the same rules as the update task, failing feeds stay backed off
+                    let now = Utc::now();
+                    let subs = subs
+                        .into_iter()
+                        .filter(|x| x.status.as_ref().is_some_and(|x| x.is_due(now)))
+                        .collect();
+                    ingest(&dbconn, &events, subs).await
+                }
+                Err(e) => {
+                    event!(
+                        Level::ERROR,
+                        backtrace = ?e,
+                        description = e.to_string(),
+                        cid
+                    );
+                }
+            }
+        });
+    }
+
+    match state.dbconn.get_channel_posts(cid, offset, limit).await {
+        Ok(posts) => Ok(Json(posts)),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                cid,
+                offset
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had some issues with the request...".to_string(),
+            ))
+        }
+    }
+}
+
+// start_refresh checks the channel hasn't been refreshed recently, recording that it's being refreshed now
+fn start_refresh(state: &Appstate, cid: u64) -> bool {
+    let now = Utc::now();
+    let cooldown = Duration::minutes(REFRESH_COOLDOWN_MINUTES);
+    let mut refreshed = state.refreshed.lock().unwrap();
+    refreshed.retain(|_, x| now - *x < cooldown);
+    if refreshed.contains_key(&cid) {
+        return false;
+    }
+    refreshed.insert(cid, now);
+    true
+}
+
+// post_events streams the channel's new posts as server-sent events, as update_feed_task stores them.
+// Each event's id is the post's id, so a client that reconnects with Last-Event-ID picks up where it left off.
+// Without one, the stream starts from the posts stored after it was opened.
+// EXPECTED QUERY PARAMS: cid
+async fn post_events(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, (StatusCode, String)> {
+    let cid = match params.get("cid").map(|x| x.parse::<u64>()) {
+        Some(Ok(val)) => val,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Missing required `cid` field".to_string(),
+            ))
+        }
+    };
+    check_channel(&state, &user, cid).await?;
+
+    // listen before looking up the latest post, so nothing stored in between is missed
+    let rx = state.events.subscribe();
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok());
+    let last_id = match last_event_id {
+        Some(val) => val,
+        None => match state.dbconn.get_latest_post_id().await {
+            Ok(val) => val,
+            Err(e) => {
+                event!(
+                    Level::ERROR,
+                    backtrace = ?e,
+                    description = e.desc,
+                    cid
+                );
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "We had some issues with the request...".to_string(),
+                ));
+            }
+        },
+    };
+
+    let posts = stream::unfold(
+        (state.dbconn, rx, last_id, VecDeque::<Post>::new()),
+        move |(dbconn, mut rx, mut last_id, mut pending)| async move {
+            loop {
+                if let Some(post) = pending.pop_front() {
+                    let event = Event::default()
+                        .event("post")
+                        .id(post.id().to_string())
+                        .json_data(&post);
+                    return Some((event, (dbconn, rx, last_id, pending)));
+                }
+                match dbconn
+                    .get_channel_posts_after(cid, last_id, EVENTS_BATCH_SIZE)
+                    .await
+                {
+                    Ok(posts) if !posts.is_empty() => {
+                        last_id = posts.iter().map(|x| x.id()).max().unwrap_or(last_id);
+                        pending.extend(posts);
+                        continue;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        event!(
+                            Level::ERROR,
+                            backtrace = ?e,
+                            description = e.desc,
+                            cid,
+                            last_id
+                        );
+                        // the client reconnects with the last id it got
+                        return None;
+                    }
+                }
+                // wait for an ingest that stored something newer
+                loop {
+                    match rx.recv().await {
+                        Ok(newest) if newest > last_id => break,
+                        Ok(_) => {}
+                        // missed some, the database has them anyway
+                        Err(RecvError::Lagged(_)) => break,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        },
+    );
+    Ok(Sse::new(posts).keep_alive(KeepAlive::default()))
+}
+
+#[debug_handler]
+async fn get_channels(
+    State(state): State<Appstate>,
+    user: AuthUser,
+) -> Result<Json<Vec<Channel>>, (StatusCode, String)> {
+    let uid = user.uid;
+    match state.dbconn.get_channels_for_user(uid).await {
+        Ok(channels) => Ok(Json(channels)),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.to_string(),
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+// import_opml expects the OPML document as the request body
+async fn import_opml(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    body: String,
+) -> Result<Json<Vec<ImportResult>>, (StatusCode, String)> {
+    let uid = user.uid;
+    match state.dbconn.import_opml(uid, &body).await {
+        Ok(report) => Ok(Json(report)),
+        // the document itself couldn't be read, nothing was imported
+        Err(e) if e.friendly_desc.is_some() => Err((
+            StatusCode::BAD_REQUEST,
+            e.friendly_desc.unwrap_or("".to_string()) + &e.desc,
+        )),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+async fn export_opml(
+    State(state): State<Appstate>,
+    user: AuthUser,
+) -> Result<Response, (StatusCode, String)> {
+    let uid = user.uid;
+    match state.dbconn.export_opml(uid).await {
+        Ok(opml) => Ok((
+            [
+                (CONTENT_TYPE, "text/x-opml; charset=utf-8"),
+                (
+                    CONTENT_DISPOSITION,
+                    "attachment; filename=\"subscriptions.opml\"",
+                ),
+            ],
+            opml,
+        )
+            .into_response()),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
             ))
         }
     }
 }
 
-#[debug_handler]
-async fn get_subs(
+// channel_feed publishes a channel's latest posts as feed.xml (rss) or feed.atom.
+// Channels with a feed token need it passed as the `token` query parameter.
+async fn channel_feed(
     State(state): State<Appstate>,
+    Path((cid, file)): Path<(u64, String)>,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<Json<Vec<Subscription>>, (StatusCode, String)> {
-    let res = params.get("cid").map(|x| x.parse::<u64>());
-    match res {
-        Some(Ok(cid)) => {
-            let urls = state.dbconn.get_subbed(cid).await;
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let not_found = (StatusCode::NOT_FOUND, "No such feed".to_string());
+    if file != "feed.xml" && file != "feed.atom" {
+        return Err(not_found);
+    }
 
-            match urls {
-                Ok(vecs) => Ok(Json(vecs)),
-                Err(e) => {
-                    event!(
-                        Level::ERROR,
-                        backtrace = ?e,
-                        description = e.to_string(),
-                    );
-                    Err((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "We had some issues with the request...".to_string(),
-                    ))
-                }
-            }
-        }
-        Some(Err(_)) => {
+    let token_hash = params.get("token").map(|x| auth::hash_token(x));
+    let res = match state
+        .dbconn
+        .get_published_channel(cid, token_hash.as_deref())
+        .await
+    {
+        // a wrong token looks the same as a missing channel
+        Ok(None) => return Err(not_found),
+        Ok(Some(channel)) => state
+            .dbconn
+            .get_channel_feed_posts(cid, FEED_PAGE_SIZE)
+            .await
+            .map(|posts| (channel, posts)),
+        Err(e) => Err(e),
+    };
+    let (channel, posts) = match res {
+        Ok(val) => val,
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+            );
             return Err((
-                StatusCode::BAD_REQUEST,
-                "Invalid ID value passed".to_string(),
-            ))
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ));
         }
-        None => match params.get("uid").map(|x| x.parse::<u64>()) {
-            Some(Ok(uid)) => {
-                let urls = state.dbconn.get_subbed_for_user(uid).await;
+    };
 
-                match urls {
-                    Ok(vecs) => Ok(Json(vecs)),
-                    Err(e) => {
-                        event!(
-                            Level::ERROR,
-                            backtrace = ?e,
-                            description = e.to_string(),
-                            uid = uid,
-                        );
-                        Err((
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "We had some issues with the request...".to_string(),
-                        ))
-                    }
-                }
-            }
-            Some(Err(_)) => {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    "Invalid ID value passed".to_string(),
-                ))
-            }
-            None => return Err((StatusCode::BAD_REQUEST, "Invalid params!".to_string())),
-        },
+    let self_url = self_url(&headers, &format!("/channel/{cid}/{file}"));
+    let description = format!("Posts from the {} channel", channel.name());
+    let meta = FeedMeta {
+        title: channel.name(),
+        description: &description,
+        self_url: &self_url,
+    };
+    let (content_type, body) = if file == "feed.xml" {
+        (
+            "application/rss+xml; charset=utf-8",
+            feed_writer::write_rss(&meta, &posts),
+        )
+    } else {
+        (
+            "application/atom+xml; charset=utf-8",
+            feed_writer::write_atom(&meta, &posts),
+        )
+    };
+    Ok(([(CONTENT_TYPE, content_type)], body).into_response())
+}
+
+// self_url rebuilds the url a request was made to, for feeds to link back to themselves
+fn self_url(headers: &HeaderMap, path: &str) -> String {
+    let host = headers
+        .get(HOST)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or("localhost:3000");
+    // only the docker deployment is served over https
+    let scheme = match env::var("IS_DOCKER_COMPOSED") {
+        Ok(_) => "https",
+        Err(_) => "http",
+    };
+    format!("{scheme}://{host}{path}")
+}
+
+#[derive(Deserialize)]
+struct FeedTokenRequest {
+    cid: u64,
+}
+
+#[derive(Serialize)]
+struct FeedToken {
+    token: String,
+}
+
+// create_feed_token makes the channel's published feed private, replacing any token it had before
+async fn create_feed_token(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Json(payload): Json<FeedTokenRequest>,
+) -> Result<Json<FeedToken>, (StatusCode, String)> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    match state
+        .dbconn
+        .set_feed_token(user.uid, payload.cid, Some(&auth::hash_token(&token)))
+        .await
+    {
+        Ok(true) => Ok(Json(FeedToken { token })),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "No such channel".to_string())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
     }
 }
 
-// Feed gets the front page aggregated posts from the user's rss feed
-// EXPECTED QUERY PARAMS: uid
-#[debug_handler]
-async fn feed(
+// delete_feed_token makes the channel's published feed public again
+async fn delete_feed_token(
     State(state): State<Appstate>,
+    user: AuthUser,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<Json<Vec<Post>>, (StatusCode, String)> {
-    let res = params.get("cid").map(|x| x.parse::<u64>());
-    let cid = match res {
+) -> Result<(), (StatusCode, String)> {
+    let uid = user.uid;
+    let cid = match params.get("cid").map(|x| x.parse::<u64>()) {
         Some(Ok(val)) => val,
         _ => {
             return Err((
                 StatusCode::BAD_REQUEST,
-                "Invalid ID value passed".to_string(),
+                "Missing required `cid` field".to_string(),
             ))
         }
     };
 
-    // get the list of all publications user is subscribed to
-    let urls = state.dbconn.get_subbed(cid).await.unwrap();
+    match state.dbconn.set_feed_token(uid, cid, None).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "No such channel".to_string())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
 
-    // using all the rss links, get all the posts from the xml feeds
-    let data = rss_parser::get_whole_feed(urls).await;
+// get_filters lists the user's filter rules
+async fn get_filters(
+    State(state): State<Appstate>,
+    user: AuthUser,
+) -> Result<Json<Vec<FilterRule>>, (StatusCode, String)> {
+    let uid = user.uid;
+    match state.dbconn.get_filters(uid).await {
+        Ok(rules) => Ok(Json(rules)),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
 
-    // insert posts into database for the 'all' page
-    let res = state.dbconn.insert_posts(&data).await;
-    if let Err(e) = res {
-        // We don't need to necessarily return an error to the consumer here
-        event!(
-            Level::ERROR,
-            backtrace = ?e,
-            description = e.to_string(),
-            // want to log data here but have to implement display i think. lazy rn.
-        );
+// post_filter adds a filter rule, which mutes matching posts as they're ingested
+async fn post_filter(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Json(payload): Json<FilterRule>,
+) -> Result<Json<FilterRule>, (StatusCode, String)> {
+    if let Some(cid) = payload.cid {
+        check_channel(&state, &user, cid).await?;
     }
+    if let Some(pid) = payload.pid {
+        check_publisher(&state, &user, pid).await?;
+    }
+    let mut rule = match payload.compile() {
+        Ok(filter) => filter.rule().clone(),
+        Err(reason) => return Err((StatusCode::BAD_REQUEST, reason)),
+    };
 
-    Ok(Json(data))
+    match state.dbconn.insert_filter(user.uid, &rule).await {
+        Ok(fid) => {
+            rule.fid = fid;
+            Ok(Json(rule))
+        }
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid = user.uid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
 }
 
-#[debug_handler]
-async fn get_channels(
+async fn delete_filter(
     State(state): State<Appstate>,
+    user: AuthUser,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<Json<Vec<Channel>>, (StatusCode, String)> {
-    let uid = match params.get("uid").map(|x| x.parse::<u64>()) {
+) -> Result<(), (StatusCode, String)> {
+    let uid = user.uid;
+    let fid = match params.get("fid").map(|x| x.parse::<u64>()) {
         Some(Ok(val)) => val,
         _ => {
             return Err((
                 StatusCode::BAD_REQUEST,
-                "Missing required `uid` field".to_string(),
+                "Missing required `fid` field".to_string(),
             ))
         }
     };
 
-    match state.dbconn.get_channels_for_user(uid).await {
-        Ok(channels) => Ok(Json(channels)),
+    match state.dbconn.delete_filter(uid, fid).await {
+        Ok(()) => Ok(()),
         Err(e) => {
             event!(
                 Level::ERROR,
                 backtrace = ?e,
-                description = e.to_string(),
+                description = e.desc,
+                uid,
+                fid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+// preview_filter shows which of the user's recent posts a rule would hit, without saving it
+async fn preview_filter(
+    State(state): State<Appstate>,
+    user: AuthUser,
+    Json(payload): Json<FilterRule>,
+) -> Result<Json<Vec<Post>>, (StatusCode, String)> {
+    let filter = match payload.compile() {
+        Ok(val) => val,
+        Err(reason) => return Err((StatusCode::BAD_REQUEST, reason)),
+    };
+
+    match state
+        .dbconn
+        .preview_filter(user.uid, &filter, FILTER_PREVIEW_POSTS)
+        .await
+    {
+        Ok(posts) => Ok(Json(posts)),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid = user.uid
             );
             Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -393,17 +1610,22 @@ async fn get_channels(
 
 #[derive(Deserialize)]
 struct CreateChannel {
-    uid: u64,
     name: String,
+    // makes it a smart channel
+    query: Option<SmartQuery>,
 }
 #[debug_handler]
 async fn post_channel(
     State(state): State<Appstate>,
+    user: AuthUser,
     Json(payload): Json<CreateChannel>,
 ) -> Result<(), (StatusCode, String)> {
+    if let Some(Err(reason)) = payload.query.as_ref().map(|x| x.validate()) {
+        return Err((StatusCode::BAD_REQUEST, reason));
+    }
     match state
         .dbconn
-        .insert_channel_for_user(payload.uid, payload.name)
+        .insert_channel_for_user(user.uid, payload.name, payload.query.as_ref())
         .await
     {
         Ok(_) => Ok(()),
@@ -423,18 +1645,10 @@ async fn post_channel(
 
 async fn delete_channel(
     State(state): State<Appstate>,
+    user: AuthUser,
     Query(params): Query<HashMap<String, String>>,
 ) -> Result<(), (StatusCode, String)> {
-    let uid = match params.get("uid").map(|x| x.parse::<u64>()) {
-        Some(Ok(val)) => val,
-        _ => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                "Missing required `uid` field".to_string(),
-            ))
-        }
-    };
-
+    let uid = user.uid;
     let cid = match params.get("cid").map(|x| x.parse::<u64>()) {
         Some(Ok(val)) => val,
         _ => {
@@ -461,18 +1675,62 @@ async fn delete_channel(
     }
 }
 
-async fn update_feed_task() {
+// check_channel answers 404 unless the channel is one of the user's
+async fn check_channel(
+    state: &Appstate,
+    user: &AuthUser,
+    cid: u64,
+) -> Result<(), (StatusCode, String)> {
+    match state.dbconn.owns_channel(user.uid, cid).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "No such channel".to_string())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid = user.uid,
+                cid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+// check_publisher answers 404 unless the user is subscribed to the publisher
+async fn check_publisher(
+    state: &Appstate,
+    user: &AuthUser,
+    pid: u64,
+) -> Result<(), (StatusCode, String)> {
+    match state.dbconn.subscribes_to(user.uid, pid).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "No such subscription".to_string())),
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.desc,
+                uid = user.uid,
+                pid
+            );
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "We had an issue with the request..".to_string(),
+            ))
+        }
+    }
+}
+
+async fn update_feed_task(events: &broadcast::Sender<u64>) {
     println!("Starting update feed task!");
     let dbconn = DatabaseConnection::new();
     let pubs = dbconn.get_all_publishers().await;
     match pubs {
-        Ok(pubs) => {
-            let data = rss_parser::get_whole_feed(pubs).await;
-            let res = dbconn.insert_posts(&data).await;
-            if let Err(e) = res {
-                DetailedError::new_descriptive(Box::new(e), "Failed auto update script");
-            }
-        }
+        Ok(pubs) => ingest(&dbconn, events, pubs).await,
         Err(e) => {
             DetailedError::new_descriptive(Box::new(e), "Failed auto update script");
         }
@@ -480,6 +1738,71 @@ async fn update_feed_task() {
     println!("Finished update feed task!")
 }
 
+async fn prune_posts_task() {
+    let dbconn = DatabaseConnection::new();
+    match dbconn.prune_posts(&RetentionPolicy::from_env()).await {
+        Ok(pruned) => event!(Level::INFO, pruned, "Finished prune posts task"),
+        Err(e) => {
+            DetailedError::new_descriptive(Box::new(e), "Failed prune posts script");
+        }
+    }
+    if let Err(e) = dbconn.delete_expired_sessions().await {
+        DetailedError::new_descriptive(Box::new(e), "Failed to delete expired sessions");
+    }
+}
+
+/// ingest fetches the given publishers' feeds and stores any new or changed posts,
+/// letting /events know once there are new ones
+async fn ingest(
+    dbconn: &DatabaseConnection,
+    events: &broadcast::Sender<u64>,
+    pubs: Vec<Subscription>,
+) {
+    let data = rss_parser::get_whole_feed(pubs).await;
+    let res = match dbconn.insert_posts(&data.posts).await {
+        // filters only see new posts, changed ones keep whatever the user did with them
+        Ok(inserted) => {
+            let res = dbconn.apply_filters(&inserted).await;
+            // only after the filters, so hidden posts are never sent.
+            // Sending fails when nobody is listening, which is fine
+            if let Some(newest) = inserted.iter().map(|(id, _)| *id).max() {
+                let _ = events.send(newest);
+            }
+            res
+        }
+        Err(e) => Err(e),
+    };
+    let res = match res {
+        // only remember the validators once the posts are safely stored
+        Ok(()) => dbconn.update_fetch_results(&data.fetched).await,
+        Err(e) => Err(e),
+    };
+    let res = match res {
+        Ok(()) => move_publishers(dbconn, &data.fetched).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        event!(
+            Level::ERROR,
+            backtrace = ?e,
+            description = e.desc,
+        );
+    }
+}
+
+// move_publishers updates the url of every publisher whose feed has permanently moved
+async fn move_publishers(
+    dbconn: &DatabaseConnection,
+    fetched: &[rss_parser::FetchResult],
+) -> Result<(), DetailedError> {
+    for r in fetched {
+        if let Some(url) = &r.moved_to {
+            dbconn.move_publisher(r.pid, url).await?;
+        }
+    }
+    Ok(())
+}
+
 // TODO:
 // script to refresh feeds
 // END TODO
@@ -0,0 +1,203 @@
+use crate::escape_xml;
+use chrono::Utc;
+use roxmltree::Node;
+use std::error::Error;
+
+// feeds that aren't in any folder are put in a channel with this name
+pub const DEFAULT_FOLDER: &str = "Imported";
+// the longest channel name the channel table can hold
+const MAX_FOLDER_NAME: usize = 50;
+
+/// OpmlFolder is a top level outline of an OPML document, which becomes a channel
+pub struct OpmlFolder {
+    pub title: String,
+    pub feeds: Vec<OpmlFeed>,
+}
+
+/// OpmlFeed is an outline pointing to a feed
+pub struct OpmlFeed {
+    pub title: Option<String>,
+    pub xml_url: String,
+    // the website the feed belongs to
+    pub html_url: Option<String>,
+}
+
+/// parse_opml reads the folders and feeds out of an OPML document.
+/// Folders nested deeper than the top level are flattened into their top level folder,
+/// and top level folders with the same name are merged.
+pub fn parse_opml(data: &str) -> Result<Vec<OpmlFolder>, Box<dyn Error>> {
+    let doc = roxmltree::Document::parse(data)?;
+    if !doc.root_element().has_tag_name("opml") {
+        return Err("Not an OPML document!".to_string().into());
+    }
+    let body = match doc
+        .root_element()
+        .children()
+        .find(|x| x.has_tag_name("body"))
+    {
+        Some(val) => val,
+        None => return Err("Malformed OPML document!".to_string().into()),
+    };
+
+    let mut folders: Vec<OpmlFolder> = vec![];
+    for outline in body.children().filter(|x| x.has_tag_name("outline")) {
+        let (title, feeds) = if outline.attribute("xmlUrl").is_some() {
+            (DEFAULT_FOLDER.to_string(), vec![outline])
+        } else {
+            let title = outline_title(outline)
+                .map(|x| x.chars().take(MAX_FOLDER_NAME).collect())
+                .unwrap_or(DEFAULT_FOLDER.to_string());
+            let feeds = outline
+                .descendants()
+                .filter(|x| x.has_tag_name("outline") && x.attribute("xmlUrl").is_some())
+                .collect();
+            (title, feeds)
+        };
+        let feeds = feeds.into_iter().map(|x| OpmlFeed {
+            title: outline_title(x),
+            xml_url: x.attribute("xmlUrl").unwrap().trim().to_string(),
+            html_url: x
+                .attribute("htmlUrl")
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty()),
+        });
+
+        match folders.iter_mut().find(|x| x.title == title) {
+            Some(folder) => folder.feeds.extend(feeds),
+            None => folders.push(OpmlFolder {
+                title,
+                feeds: feeds.collect(),
+            }),
+        }
+    }
+    // a folder with nothing in it would just make an empty channel
+    folders.retain(|x| !x.feeds.is_empty());
+    Ok(folders)
+}
+
+/// write_opml builds an OPML 2.0 document with an outline for every folder,
+/// each holding an outline for every feed in it
+pub fn write_opml(title: &str, folders: &[OpmlFolder]) -> String {
+    let mut out =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n");
+    out.push_str(&format!(
+        "  <head>\n    <title>{}</title>\n    <dateCreated>{}</dateCreated>\n  </head>\n  <body>\n",
+        escape_xml(title),
+        Utc::now().to_rfc2822()
+    ));
+    for folder in folders {
+        let title = escape_xml(&folder.title);
+        out.push_str(&format!(
+            "    <outline text=\"{title}\" title=\"{title}\">\n"
+        ));
+        for feed in &folder.feeds {
+            let title = escape_xml(feed.title.as_deref().unwrap_or(&feed.xml_url));
+            out.push_str(&format!(
+                "      <outline type=\"rss\" text=\"{title}\" title=\"{title}\" xmlUrl=\"{}\"",
+                escape_xml(&feed.xml_url)
+            ));
+            if let Some(html_url) = &feed.html_url {
+                out.push_str(&format!(" htmlUrl=\"{}\"", escape_xml(html_url)));
+            }
+            out.push_str("/>\n");
+        }
+        out.push_str("    </outline>\n");
+    }
+    out.push_str("  </body>\n</opml>\n");
+    out
+}
+
+// text is the attribute OPML requires, but some exporters only set title
+fn outline_title(outline: Node) -> Option<String> {
+    outline
+        .attribute("text")
+        .or(outline.attribute("title"))
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+}
+
+#[cfg(test)]
+mod opml_tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_parse_opml() {
+        let data = fs::read_to_string("test-files/subscriptions.opml").unwrap();
+        let folders = match parse_opml(&data) {
+            Ok(val) => val,
+            Err(error) => panic!("{error}"),
+        };
+
+        let titles: Vec<&str> = folders.iter().map(|x| x.title.as_str()).collect();
+        assert_eq!(titles, vec!["Tech", DEFAULT_FOLDER, "News"]);
+
+        // nested folders and the second "Tech" folder all end up in the first one
+        let urls: Vec<&str> = folders[0]
+            .feeds
+            .iter()
+            .map(|x| x.xml_url.as_str())
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                "https://www.theverge.com/rss/index.xml",
+                "https://feeds.arstechnica.com/arstechnica/index",
+                "https://www.wired.com/feed/rss",
+            ]
+        );
+        assert_eq!(folders[0].feeds[0].title, Some("The Verge".to_string()));
+        assert_eq!(folders[1].feeds[0].xml_url, "https://example.com/feed.xml");
+        // outlines without a url aren't feeds
+        assert_eq!(folders[2].feeds.len(), 1);
+    }
+
+    #[test]
+    fn test_write_opml() {
+        let folders = vec![
+            OpmlFolder {
+                title: "News & \"Views\"".to_string(),
+                feeds: vec![OpmlFeed {
+                    title: Some("<Tech>".to_string()),
+                    xml_url: "https://example.com/feed?a=1&b=2".to_string(),
+                    html_url: Some("https://example.com/".to_string()),
+                }],
+            },
+            OpmlFolder {
+                title: "Other".to_string(),
+                feeds: vec![OpmlFeed {
+                    title: None,
+                    xml_url: "https://example.org/rss.xml".to_string(),
+                    html_url: None,
+                }],
+            },
+        ];
+        let data = write_opml("Subscriptions", &folders);
+
+        // reading it back should give us what we wrote
+        let read = match parse_opml(&data) {
+            Ok(val) => val,
+            Err(error) => panic!("{error}"),
+        };
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].title, "News & \"Views\"");
+        assert_eq!(read[0].feeds[0].title, Some("<Tech>".to_string()));
+        assert_eq!(read[0].feeds[0].xml_url, "https://example.com/feed?a=1&b=2");
+        assert_eq!(
+            read[0].feeds[0].html_url,
+            Some("https://example.com/".to_string())
+        );
+        // feeds without a title are named after their url
+        assert_eq!(
+            read[1].feeds[0].title,
+            Some("https://example.org/rss.xml".to_string())
+        );
+        assert_eq!(read[1].feeds[0].html_url, None);
+    }
+
+    #[test]
+    fn test_parse_opml_rejects_other_xml() {
+        let data = fs::read_to_string("test-files/atom.xml").unwrap();
+        assert!(parse_opml(&data).is_err());
+    }
+}
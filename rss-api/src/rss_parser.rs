@@ -1,75 +1,305 @@
 use super::*;
-use chrono::{NaiveDateTime, TimeZone};
+use crate::logger::DetailedError;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use futures_util::future;
+use lazy_static::lazy_static;
 use roxmltree::Node;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Semaphore};
 use tracing::{event, Level};
 
+const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+// how many redirects to follow before giving up on a feed
+const MAX_REDIRECTS: usize = 10;
+// where feeds usually live, tried when a page doesn't link to its feed
+const FEED_PATHS: [&str; 3] = ["/feed", "/rss.xml", "/atom.xml"];
+// how long looking for a feed can wait on a site, so a slow one can't hold up subscribing
+const DISCOVERY_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(15);
+
+lazy_static! {
+    // shared by everything that looks for and validates feeds, so connections are reused
+    static ref DISCOVERY_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .connect_timeout(DISCOVERY_CONNECT_TIMEOUT)
+        .timeout(DISCOVERY_TIMEOUT)
+        .build()
+        .unwrap();
+}
+
+/// WholeFeed is everything that came back from fetching a list of subscriptions
+pub struct WholeFeed {
+    pub posts: Vec<Post>,
+    // one result for every publisher that was fetched, whether it worked or not
+    pub fetched: Vec<FetchResult>,
+}
+
+/// FetchResult records how fetching a publisher's feed went
+pub struct FetchResult {
+    pub pid: u64,
+    // the http status of the response, None if we never got one
+    pub status: Option<u16>,
+    // why the fetch failed, None if it worked
+    pub error: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // where the feed now lives, if it was permanently redirected
+    pub moved_to: Option<String>,
+}
+
+impl FetchResult {
+    fn failed(sub: &Subscription, status: Option<u16>, error: String) -> Self {
+        FetchResult {
+            pid: sub.pid.unwrap(),
+            status,
+            error: Some(error),
+            etag: None,
+            last_modified: None,
+            moved_to: None,
+        }
+    }
+}
+
+/// FetchedFeed is sent back over the channel for every subscription once it's been fetched
+pub struct FetchedFeed {
+    pub result: FetchResult,
+    pub posts: Vec<Post>,
+}
+
+/// FeedFetch is the response to a (possibly conditional) request for a feed.
+/// moved_to is set when every redirect on the way there was permanent (301 or 308)
+pub enum FeedFetch {
+    // the feed hasn't changed since the validators we sent
+    NotModified {
+        moved_to: Option<String>,
+    },
+    Fetched {
+        status: u16,
+        body: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        moved_to: Option<String>,
+    },
+}
+
+/// FetchConfig controls how many feeds are fetched at once and how politely.
+/// Each setting can be overridden with an environment variable, see from_env.
+#[derive(Clone)]
+pub struct FetchConfig {
+    // requests in flight across all hosts
+    pub max_concurrent: usize,
+    // requests in flight to any one host
+    pub max_per_host: usize,
+    // time to wait between starting requests to the same host
+    pub host_delay: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            max_concurrent: 16,
+            max_per_host: 2,
+            host_delay: Duration::from_millis(500),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl FetchConfig {
+    /// from_env reads RSS_FETCH_CONCURRENCY, RSS_FETCH_PER_HOST, RSS_FETCH_HOST_DELAY_MS
+    /// and RSS_FETCH_TIMEOUT_SECS, using the defaults for anything unset or invalid
+    pub fn from_env() -> Self {
+        fn var(name: &str) -> Option<u64> {
+            env::var(name).ok().and_then(|x| x.parse::<u64>().ok())
+        }
+        let default = FetchConfig::default();
+        FetchConfig {
+            max_concurrent: var("RSS_FETCH_CONCURRENCY")
+                .map(|x| x.max(1) as usize)
+                .unwrap_or(default.max_concurrent),
+            max_per_host: var("RSS_FETCH_PER_HOST")
+                .map(|x| x.max(1) as usize)
+                .unwrap_or(default.max_per_host),
+            host_delay: var("RSS_FETCH_HOST_DELAY_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(default.host_delay),
+            timeout: var("RSS_FETCH_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.timeout),
+        }
+    }
+}
+
+// HostLimiter keeps us from hammering a single host
+struct HostLimiter {
+    permits: Semaphore,
+    // when the last request to the host was started
+    last_request: tokio::sync::Mutex<Option<Instant>>,
+}
+
 /// get_whole_feed expects a list of urls to get feed data from
-pub async fn get_whole_feed(urls: Vec<Subscription>) -> Vec<Post> {
-    let vec: Arc<Mutex<Vec<Post>>> = Arc::new(Mutex::new(Vec::new()));
-    let mut handles = vec![];
+pub async fn get_whole_feed(urls: Vec<Subscription>) -> WholeFeed {
+    let mut rx = fetch_feeds(urls, FetchConfig::from_env());
+    let mut posts = vec![];
+    let mut fetched = vec![];
+    while let Some(mut feed) = rx.recv().await {
+        posts.append(&mut feed.posts);
+        fetched.push(feed.result);
+    }
+    WholeFeed { posts, fetched }
+}
+
+/// fetch_feeds fetches and parses every subscription's feed, limited by the config.
+/// Each feed is sent back over the returned channel as soon as it's done,
+/// and the channel closes once all of them are.
+pub fn fetch_feeds(urls: Vec<Subscription>, config: FetchConfig) -> mpsc::Receiver<FetchedFeed> {
+    let (tx, rx) = mpsc::channel(32);
+    // redirects are followed by hand in fetch_feed so permanent moves can be recorded
+    let client = match reqwest::Client::builder()
+        .timeout(config.timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+    {
+        Ok(val) => val,
+        Err(e) => {
+            // no client means nothing can be fetched, so the channel just closes
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.to_string()
+            );
+            return rx;
+        }
+    };
+    let global = Arc::new(Semaphore::new(config.max_concurrent));
+    let mut hosts: HashMap<String, Arc<HostLimiter>> = HashMap::new();
 
     for sub in urls {
-        let vector = Arc::clone(&vec);
-        let handle = tokio::spawn(async move {
-            let data = from_url(&sub.url).await;
-            let data = match data {
-                Ok(val) => val,
-                Err(e) => {
-                    // If this errors, means that the request to the url failed.
-                    // We don't have to full-blown error here because other resources could still work.
-                    event!(
-                        Level::ERROR,
-                        backtrace = ?e,
-                        description = e.to_string()
-                    );
-                    return;
-                }
-            };
-            // println!("parsing feed for {}...", &url.url);
-            let res = parse_feed(&data, &sub).await;
-            match res {
-                Ok(mut posts) => {
-                    let mut vector = vector.lock().unwrap();
-                    vector.append(&mut posts);
-                    // println!("Finished parsing feed for {}!", &url.url);
-                }
-                // Again, we don't have to error here as other rss feeds may still parse well => may be ill-formed xml
-                Err(e) => {
-                    event!(
-                        Level::ERROR,
-                        backtrace = ?e,
-                        description = e.to_string(),
-                        url = sub.url
-                    )
+        let host = url::Url::parse(&sub.url)
+            .ok()
+            .and_then(|x| x.host_str().map(|x| x.to_owned()))
+            .unwrap_or_default();
+        let limiter = hosts.entry(host).or_insert_with(|| {
+            Arc::new(HostLimiter {
+                permits: Semaphore::new(config.max_per_host),
+                last_request: tokio::sync::Mutex::new(None),
+            })
+        });
+        let limiter = Arc::clone(limiter);
+        let global = Arc::clone(&global);
+        let client = client.clone();
+        let tx = tx.clone();
+        let delay = config.host_delay;
+
+        tokio::spawn(async move {
+            // wait on the host first, so we don't hold up other hosts while we're queued
+            let _host_permit = limiter.permits.acquire().await;
+            {
+                let mut last = limiter.last_request.lock().await;
+                if let Some(at) = *last {
+                    let elapsed = at.elapsed();
+                    if elapsed < delay {
+                        tokio::time::sleep(delay - elapsed).await;
+                    }
                 }
+                *last = Some(Instant::now());
             }
+            let _permit = global.acquire().await;
+            let feed = fetch_and_parse(&client, sub).await;
+            // the receiver only goes away if the caller stopped listening
+            let _ = tx.send(feed).await;
         });
-        handles.push(handle);
-    }
-
-    for handle in handles {
-        // we should handle this.
-        let _ = handle.await;
     }
+    rx
+}
 
-    // We don't need the Arc anymore as all the async tasks are done.
-    // We call try_unwrap followed by unwrap() as we know it won't fail
-    let data = Arc::try_unwrap(vec).unwrap();
-    // We call into_inner to move the value out of the mutex as we don't need the mutex anymore.
-    let data = data.into_inner();
-
-    match data {
-        Ok(data) => data,
+// fetch_and_parse gets the posts from one subscription
+async fn fetch_and_parse(client: &reqwest::Client, sub: Subscription) -> FetchedFeed {
+    let data = fetch_feed(client, &sub).await;
+    let (status, data, etag, last_modified, moved_to) = match data {
+        Ok(FeedFetch::Fetched {
+            status,
+            body,
+            etag,
+            last_modified,
+            moved_to,
+        }) => (status, body, etag, last_modified, moved_to),
+        // nothing new, keep the validators we already have
+        Ok(FeedFetch::NotModified { moved_to }) => {
+            return FetchedFeed {
+                result: FetchResult {
+                    pid: sub.pid.unwrap(),
+                    status: Some(304),
+                    error: None,
+                    etag: sub.etag,
+                    last_modified: sub.last_modified,
+                    moved_to,
+                },
+                posts: vec![],
+            }
+        }
         Err(e) => {
-            // Mutex got poisoned somehow.
+            // If this errors, means that the request to the url failed.
+            // We don't have to full-blown error here because other resources could still work.
             event!(
                 Level::ERROR,
                 backtrace = ?e,
                 description = e.to_string()
             );
-            vec![]
+            let status = e
+                .downcast_ref::<reqwest::Error>()
+                .and_then(|x| x.status())
+                .map(|x| x.as_u16());
+            return FetchedFeed {
+                result: FetchResult::failed(&sub, status, e.to_string()),
+                posts: vec![],
+            };
+        }
+    };
+    // println!("parsing feed for {}...", &url.url);
+    let res = parse_feed(&data, &sub).await;
+    match res {
+        Ok(feed) => {
+            // a few bad items shouldn't throw away the rest, but we still want to know about them
+            if let Some(report) = feed.report() {
+                let e = DetailedError::new_with_message(&report);
+                event!(
+                    Level::ERROR,
+                    backtrace = ?e,
+                    description = e.desc,
+                    url = sub.url
+                );
+            }
+            // println!("Finished parsing feed for {}!", &url.url);
+            FetchedFeed {
+                result: FetchResult {
+                    pid: sub.pid.unwrap(),
+                    status: Some(status),
+                    error: None,
+                    etag,
+                    last_modified,
+                    moved_to,
+                },
+                posts: feed.posts,
+            }
+        }
+        // Again, we don't have to error here as other rss feeds may still parse well => may be ill-formed xml
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                backtrace = ?e,
+                description = e.to_string(),
+                url = sub.url
+            );
+            FetchedFeed {
+                result: FetchResult::failed(&sub, Some(status), e.to_string()),
+                posts: vec![],
+            }
         }
     }
 }
@@ -79,13 +309,141 @@ pub async fn from_url(url: &str) -> Result<String, reqwest::Error> {
     Ok(val)
 }
 
-/// parse_feed takes in a slice of data representing the xml of the feed
-/// it then checks if the file is a valid rss/atom feed, if not it just tries both.
-/// Returns a vector of posts in the feed, or an Error
-async fn parse_feed<'a>(
+/// fetch_feed requests the subscription's feed, sending along the cache validators
+/// from the last fetch so an unchanged feed can answer 304 Not Modified.
+/// Redirects are followed up to MAX_REDIRECTS, expecting a client that doesn't follow them itself
+pub async fn fetch_feed(
+    client: &reqwest::Client,
+    sub: &Subscription,
+) -> Result<FeedFetch, Box<dyn Error + Send + Sync>> {
+    let mut url = sub.url.clone();
+    let mut moved_to = None;
+    // stays true until we hit a temporary redirect, after which nothing further is a permanent move
+    let mut permanent = true;
+    let mut redirects = 0;
+
+    let res = loop {
+        let mut req = client.get(&url);
+        if let Some(etag) = &sub.etag {
+            req = req.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &sub.last_modified {
+            req = req.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+        let res = req.send().await?;
+        if !res.status().is_redirection() || res.status() == reqwest::StatusCode::NOT_MODIFIED {
+            break res;
+        }
+
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(format!("too many redirects fetching {}", sub.url).into());
+        }
+        let location = res
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|x| x.to_str().ok())
+            .ok_or_else(|| format!("redirect from {} has no location", url))?;
+        // the location can be relative to the url that sent it
+        let next = url::Url::parse(&url)?.join(location)?.to_string();
+        match res.status() {
+            reqwest::StatusCode::MOVED_PERMANENTLY | reqwest::StatusCode::PERMANENT_REDIRECT
+                if permanent =>
+            {
+                moved_to = Some(next.clone())
+            }
+            _ => permanent = false,
+        }
+        url = next;
+    };
+
+    if res.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(FeedFetch::NotModified { moved_to });
+    }
+    let res = res.error_for_status()?;
+    let status = res.status().as_u16();
+
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .map(|x| x.to_owned())
+    };
+    let etag = header(reqwest::header::ETAG);
+    let last_modified = header(reqwest::header::LAST_MODIFIED);
+    let body = res.text().await?;
+    Ok(FeedFetch::Fetched {
+        status,
+        body,
+        etag,
+        last_modified,
+        moved_to,
+    })
+}
+
+/// ParsedFeed holds the posts that could be read from a feed,
+/// along with the items that had to be skipped and why.
+pub struct ParsedFeed {
+    pub posts: Vec<Post>,
+    pub skipped: Vec<SkippedItem>,
+}
+
+/// SkippedItem records an item that could not be turned into a post.
+/// index is the item's position in the feed (starting from 0)
+pub struct SkippedItem {
+    pub index: usize,
+    pub reason: String,
+}
+
+impl ParsedFeed {
+    fn new() -> Self {
+        ParsedFeed {
+            posts: Vec::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    // adds the post to the feed, or records why the item was skipped
+    fn push(&mut self, index: usize, item: Result<Post, Box<dyn Error>>) {
+        match item {
+            Ok(post) => self.posts.push(post),
+            Err(e) => self.skipped.push(SkippedItem {
+                index,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    /// report returns a summary of the skipped items, or None if nothing was skipped
+    pub fn report(&self) -> Option<String> {
+        if self.skipped.is_empty() {
+            return None;
+        }
+        let reasons: Vec<String> = self
+            .skipped
+            .iter()
+            .map(|x| format!("item {}: {}", x.index, x.reason))
+            .collect();
+        Some(format!(
+            "Skipped {} item(s): {}",
+            self.skipped.len(),
+            reasons.join("; ")
+        ))
+    }
+}
+
+/// parse_feed takes in a slice of data representing the xml (or json) of the feed
+/// it then checks if the file is a valid rss/atom/json feed, if not it just tries rss and atom.
+/// Returns the posts in the feed along with any skipped items, or an Error
+pub(crate) async fn parse_feed<'a>(
     data: &'a str,
     publisher: &Subscription,
-) -> Result<Vec<Post>, Box<dyn Error>> {
+) -> Result<ParsedFeed, Box<dyn Error>> {
+    // json feeds aren't xml, so check for them before handing the data to roxmltree
+    if is_json_feed(data) {
+        return parse_json(data, publisher);
+    }
+
     let doc = roxmltree::Document::parse(data);
     let doc = match doc {
         Ok(val) => val,
@@ -98,6 +456,8 @@ async fn parse_feed<'a>(
 
     match ver {
         Some(Some(i)) if i == "2.0" || i == "0.92" || i == "0.91" => parse_rss(doc, publisher),
+        // rss 1.0 has no version attribute, it's rooted at <rdf:RDF> instead
+        _ if doc.root_element().has_tag_name("RDF") => parse_rdf(doc, publisher),
         _ => {
             // try to find the feed element that signifies atom
             let feed = &doc.descendants().find(|x| x.has_tag_name("feed"));
@@ -105,288 +465,727 @@ async fn parse_feed<'a>(
                 parse_atom(doc, publisher)
             } else {
                 // hail mary - run rss then parse atom cos we ball like that
-                let res = parse_rss(doc, publisher);
-                match res {
-                    Ok(val) => Ok(val),
-                    // we read in the data again
-                    // we choose to do this instead of using a borrow because i'm too lazy to refactor parse_xxx to take in a borrow
-                    // it's also more performant (FAKE)
-                    Err(_) => {
-                        match parse_atom(roxmltree::Document::parse(data).unwrap(), publisher) {
-                            Ok(val) => Ok(val),
-                            Err(_) => {
-                                return Err("Invalid feed format! (Tried running hail mary)"
-                                    .to_string()
-                                    .into())
-                            }
-                        }
-                    }
+                let res = parse_rss(doc, publisher)?;
+                if !res.posts.is_empty() {
+                    return Ok(res);
                 }
+                // we read in the data again
+                // we choose to do this instead of using a borrow because i'm too lazy to refactor parse_xxx to take in a borrow
+                // it's also more performant (FAKE)
+                let res = parse_atom(roxmltree::Document::parse(data).unwrap(), publisher)?;
+                if !res.posts.is_empty() {
+                    return Ok(res);
+                }
+                Err("Invalid feed format! (Tried running hail mary)"
+                    .to_string()
+                    .into())
             }
         }
     }
 }
 
-/// parse_rss returns a vector of posts or an error string
+/// derive_title makes a title out of the start of some text, for items that don't have one
+fn derive_title(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let mut title: String = text.chars().take(100).collect();
+    if title.len() < text.len() {
+        title.push('…');
+    }
+    Some(title)
+}
+
+/// parse_rss returns the posts in an rss feed, skipping over any malformed items
 fn parse_rss<'a>(
     doc: roxmltree::Document,
     publisher: &Subscription,
-) -> Result<Vec<Post>, Box<dyn Error>> {
-    let mut vec: Vec<Post> = Vec::new();
+) -> Result<ParsedFeed, Box<dyn Error>> {
+    let mut feed = ParsedFeed::new();
     let items = doc.descendants().filter(|x| x.has_tag_name("item"));
 
-    for item in items {
-        let nodes: Vec<Node> = item.descendants().collect();
+    for (index, item) in items.enumerate() {
+        feed.push(index, parse_rss_item(item, publisher));
+    }
+    Ok(feed)
+}
 
-        // maybe a macro? ->-
-        let title = nodes
-            .iter()
-            .find(|x| x.has_tag_name("title"))
-            .map(|x| x.text());
+/// parse_rss_item returns a post or an error string
+fn parse_rss_item(item: Node, publisher: &Subscription) -> Result<Post, Box<dyn Error>> {
+    let nodes: Vec<Node> = item.descendants().collect();
 
-        let title = match title {
-            Some(Some(t)) => t.to_owned(),
-            _ => {
-                return Err(
-                    "Missing required field title, or it's in the incorrect format!"
-                        .to_string()
-                        .into(),
-                )
-            }
-        };
+    let guid = nodes
+        .iter()
+        .find(|x| x.has_tag_name("guid"))
+        .and_then(|x| x.text())
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty());
 
-        let link = nodes
-            .iter()
-            .find(|x| x.has_tag_name("link"))
-            .map(|x| x.text());
+    let link = nodes
+        .iter()
+        .find(|x| x.has_tag_name("link"))
+        .map(|x| x.text());
 
-        let link = match link {
-            Some(Some(t)) => t.to_owned(),
-            _ => {
-                return Err(
-                    "Missing required field link, or it's in the incorrect format!"
-                        .to_string()
-                        .into(),
-                )
+    let link = match link {
+        Some(Some(t)) => t.to_owned(),
+        _ => {
+            // the guid is a permalink unless it says otherwise
+            let guid = nodes.iter().find(|x| {
+                x.has_tag_name("guid") && x.attribute("isPermaLink").unwrap_or("true") == "true"
+            });
+            match guid.map(|x| x.text()) {
+                Some(Some(t)) => t.trim().to_owned(),
+                _ => {
+                    return Err(
+                        "Missing required field link, or it's in the incorrect format!"
+                            .to_string()
+                            .into(),
+                    )
+                }
             }
-        };
+        }
+    };
 
-        let date = nodes
-            .iter()
-            .find(|x| x.has_tag_name("pubDate"))
-            .map(|x| x.text());
+    let date = nodes
+        .iter()
+        .find(|x| x.has_tag_name("pubDate"))
+        .map(|x| x.text());
 
-        let date = match date {
-            Some(Some(text)) => match_date(text),
-            _ => Utc::now(),
-        };
+    let date = match date {
+        Some(Some(text)) => match_date(text),
+        _ => Utc::now(),
+    };
 
-        let content = nodes
-            .iter()
-            .find(|x| x.has_tag_name("encoded"))
-            .map(|x| x.text());
-
-        let content = match content {
-            Some(Some(c)) => {
-                let cleaned = web_scraper::clean_html(c, None);
-                Some(cleaned.to_string())
-            }
-            _ => {
-                // attempt to get content by using content tag
-                let content = nodes
-                    .iter()
-                    .find(|x| x.has_tag_name("content"))
-                    .map(|x| x.text());
-                match content {
-                    Some(Some(c)) => Some(c.to_owned()),
-                    _ => None,
-                }
+    let content = nodes
+        .iter()
+        .find(|x| x.has_tag_name("encoded"))
+        .map(|x| x.text());
+
+    let content = match content {
+        Some(Some(c)) => {
+            let cleaned = web_scraper::clean_html(c, None);
+            Some(cleaned.to_string())
+        }
+        _ => {
+            // attempt to get content by using content tag
+            let content = nodes
+                .iter()
+                .find(|x| x.has_tag_name("content"))
+                .map(|x| x.text());
+            match content {
+                Some(Some(c)) => Some(c.to_owned()),
+                _ => None,
             }
-        };
+        }
+    };
 
-        let description = nodes
-            .iter()
-            .find(|x| x.has_tag_name("description"))
-            .map(|x| x.text());
-
-        let description = match description {
-            Some(Some(t)) => {
-                let cleaned = web_scraper::clean_html(t, None);
-                let text = cleaned.raw;
-                match text.as_str() {
-                    "" => t.to_string(),
-                    _ => text,
-                }
+    let description = nodes
+        .iter()
+        .find(|x| x.has_tag_name("description"))
+        .map(|x| x.text());
+
+    let description = match description {
+        Some(Some(t)) => {
+            let cleaned = web_scraper::clean_html(t, None);
+            let text = cleaned.raw;
+            match text.as_str() {
+                "" => t.to_string(),
+                _ => text,
             }
-            _ => {
-                // Create the description field from content if possible
-                match &content {
-                    Some(val) => {
-                        let len = std::cmp::min(100, val.len());
-                        val[0..len].to_string()
-                    }
-                    None => "[No description provided]".to_string(),
+        }
+        _ => {
+            // Create the description field from content if possible
+            match &content {
+                Some(val) => val.chars().take(100).collect(),
+                None => "[No description provided]".to_string(),
+            }
+        }
+    };
+
+    let title = nodes
+        .iter()
+        .find(|x| x.has_tag_name("title"))
+        .map(|x| x.text());
+
+    let title = match title {
+        Some(Some(t)) => t.to_owned(),
+        _ => {
+            // micro posts often leave out the title, so make one from the description
+            let derived = match description.as_str() {
+                "[No description provided]" => None,
+                text => derive_title(text),
+            };
+            match derived {
+                Some(t) => t,
+                None => {
+                    return Err(
+                        "Missing required field title, or it's in the incorrect format!"
+                            .to_string()
+                            .into(),
+                    )
                 }
             }
-        };
+        }
+    };
 
-        let enclosure = nodes.iter().find(|x| x.has_tag_name("enclosure"));
+    // <author> is meant to be an email address, most feeds use dc:creator instead
+    let author = join_authors(
+        item.children()
+            .filter(|x| x.has_tag_name("author") || x.has_tag_name("creator"))
+            .filter_map(|x| x.text()),
+    );
 
-        let enclosure: Option<String> = match enclosure {
-            Some(d) => d.attribute("url").map(|x| x.to_owned()),
-            None => None,
-        };
+    let enclosure = nodes.iter().find(|x| x.has_tag_name("enclosure"));
 
-        let post = Post {
-            // placeholder id value - won't be inserted so we don't have to worry
-            id: 999999999,
-            title,
-            link,
-            date,
-            description: description,
-            content,
-            enclosure,
-            pid: publisher.pid.unwrap(),
-            publisher_name: Some(publisher.name.to_string()),
-        };
-        vec.push(post);
-    }
-    Ok(vec)
+    let enclosure: Option<String> = match enclosure {
+        Some(d) => d.attribute("url").map(|x| x.to_owned()),
+        None => None,
+    };
+
+    Ok(Post {
+        // placeholder id value - won't be inserted so we don't have to worry
+        id: 999999999,
+        title,
+        link,
+        date,
+        description,
+        content,
+        enclosure,
+        pid: publisher.pid.unwrap(),
+        publisher_name: Some(publisher.name.to_string()),
+        author,
+        guid,
+        updated: None,
+        revisions: None,
+        read: None,
+        starred: None,
+        snippet: None,
+    })
 }
-/// parse atom returns a vector of posts or an error string.
+
+// join_authors puts an item's authors together, or gives nothing if it doesn't have any
+fn join_authors<'a>(names: impl Iterator<Item = &'a str>) -> Option<String> {
+    let names: Vec<&str> = names.map(|x| x.trim()).filter(|x| !x.is_empty()).collect();
+    Some(names.join(", ")).filter(|x| !x.is_empty())
+}
+
+/// parse atom returns the posts in an atom feed, skipping over any malformed entries
 fn parse_atom<'a>(
     doc: roxmltree::Document,
     publisher: &Subscription,
-) -> Result<Vec<Post>, Box<dyn Error>> {
-    let mut vec: Vec<Post> = Vec::new();
+) -> Result<ParsedFeed, Box<dyn Error>> {
+    let mut feed = ParsedFeed::new();
 
     let items = doc.descendants().filter(|x| x.has_tag_name("entry"));
 
-    for item in items {
-        let nodes: Vec<Node> = item.descendants().collect();
+    for (index, item) in items.enumerate() {
+        feed.push(index, parse_atom_entry(item, publisher));
+    }
+    Ok(feed)
+}
 
-        // maybe a macro? ->-
-        let title = nodes
-            .iter()
-            .find(|x| x.has_tag_name("title"))
-            .map(|x| x.text());
+/// parse_atom_entry returns a post or an error string
+fn parse_atom_entry(item: Node, publisher: &Subscription) -> Result<Post, Box<dyn Error>> {
+    let nodes: Vec<Node> = item.descendants().collect();
 
-        let title = match title {
-            Some(Some(t)) => t.to_owned(),
-            _ => {
-                return Err(
-                    "Missing required field title, or it's in the incorrect format!"
-                        .to_string()
-                        .into(),
-                )
+    // only look at the entry's own id, a <source> element can have one too
+    let guid = item
+        .children()
+        .find(|x| x.has_tag_name("id"))
+        .and_then(|x| x.text())
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty());
+
+    let link_tag = nodes
+        .iter()
+        .find(|x| x.has_tag_name("link"))
+        .map(|x| x.attribute("href"));
+
+    let link = match link_tag {
+        Some(Some(href)) => href.to_owned(),
+        _ => {
+            let link_node = nodes
+                .iter()
+                .find(|x| x.has_tag_name("id"))
+                .map(|x| x.text());
+            match link_node {
+                Some(Some(t)) => t.to_owned(),
+                _ => {
+                    return Err(
+                        "Missing required field link, or it's in the incorrect format!"
+                            .to_string()
+                            .into(),
+                    )
+                }
             }
-        };
+        }
+    };
 
-        let link_tag = nodes
-            .iter()
-            .find(|x| x.has_tag_name("link"))
-            .map(|x| x.attribute("href"));
-
-        let link = match link_tag {
-            Some(Some(href)) => href.to_owned(),
-            _ => {
-                let link_node = nodes
-                    .iter()
-                    .find(|x| x.has_tag_name("id"))
-                    .map(|x| x.text());
-                match link_node {
-                    Some(Some(t)) => t.to_owned(),
-                    _ => {
-                        return Err(
-                            "Missing required field link, or it's in the incorrect format!"
-                                .to_string()
-                                .into(),
-                        )
+    let date = nodes
+        .iter()
+        .find(|x| x.has_tag_name("published"))
+        .map(|x| x.text());
+
+    let date: DateTime<Utc> = match date {
+        Some(Some(d)) => {
+            let res = DateTime::parse_from_rfc3339(d);
+            match res {
+                Ok(dt) => dt.to_utc(),
+                Err(_) => Utc::now(),
+            }
+        }
+        _ => Utc::now(),
+    };
+
+    let updated = item
+        .children()
+        .find(|x| x.has_tag_name("updated"))
+        .and_then(|x| x.text())
+        .and_then(|d| DateTime::parse_from_rfc3339(d.trim()).ok())
+        .map(|d| d.to_utc());
+
+    let content = nodes.iter().find(|x| x.has_tag_name("content"));
+    let mut raw_content: Option<String> = None;
+    let content = match content {
+        Some(node) => {
+            let text = node.text();
+            match text {
+                Some(text) => match node.attribute("type") {
+                    Some("html") => {
+                        let cleaned = web_scraper::clean_html(text, None);
+                        raw_content = Some(cleaned.raw.to_string());
+                        Some(cleaned.to_string())
                     }
-                }
+                    _ => Some(text.to_string()),
+                },
+                None => None,
             }
-        };
+        }
+        None => None,
+    };
 
-        let date = nodes
-            .iter()
-            .find(|x| x.has_tag_name("published"))
-            .map(|x| x.text());
-
-        let date: DateTime<Utc> = match date {
-            Some(Some(d)) => {
-                let res = DateTime::parse_from_rfc3339(d);
-                match res {
-                    Ok(dt) => dt.to_utc(),
-                    Err(_) => Utc::now(),
-                }
+    let description = nodes
+        .iter()
+        .find(|x| x.has_tag_name("summary"))
+        .map(|x| x.text());
+
+    let description = match description {
+        Some(Some(t)) => {
+            let to_clean = t.to_owned();
+            let cleaned = web_scraper::clean_html(&to_clean, None);
+            cleaned.raw
+        }
+        _ => {
+            // Create the description field
+            // first check raw content to make description. Then, if not, use content
+            match &raw_content {
+                Some(val) => val.chars().take(250).collect(),
+                None => match &content {
+                    Some(val) => val.chars().take(250).collect(),
+                    None => "[No description provided]".to_string(),
+                },
             }
-            _ => Utc::now(),
-        };
+        }
+    };
 
-        let content = nodes.iter().find(|x| x.has_tag_name("content"));
-        let mut raw_content: Option<String> = None;
-        let content = match content {
-            Some(node) => {
-                let text = node.text();
-                match text {
-                    Some(text) => match node.attribute("type") {
-                        Some(val) if val == "html" => {
-                            let cleaned = web_scraper::clean_html(text, None);
-                            raw_content = Some(cleaned.raw.to_string());
-                            Some(cleaned.to_string())
-                        }
-                        _ => Some(text.to_string()),
-                    },
-                    None => None,
+    let title = nodes
+        .iter()
+        .find(|x| x.has_tag_name("title"))
+        .map(|x| x.text());
+
+    let title = match title {
+        Some(Some(t)) => t.to_owned(),
+        _ => {
+            // make a title out of the text of the entry if possible
+            let derived = match description.as_str() {
+                "[No description provided]" => None,
+                text => derive_title(text),
+            };
+            match derived {
+                Some(t) => t,
+                None => {
+                    return Err(
+                        "Missing required field title, or it's in the incorrect format!"
+                            .to_string()
+                            .into(),
+                    )
                 }
             }
-            None => None,
-        };
+        }
+    };
 
-        let description = nodes
-            .iter()
-            .find(|x| x.has_tag_name("summary"))
-            .map(|x| x.text());
-
-        let description = match description {
-            Some(Some(t)) => {
-                let to_clean = t.to_owned();
-                let cleaned = web_scraper::clean_html(&to_clean, None);
-                cleaned.raw
-            }
-            _ => {
-                // Create the description field
-                // first check raw content to make description. Then, if not, use content
-                match raw_content {
-                    Some(val) => {
-                        let len = std::cmp::min(250, val.len());
-                        val[0..len].to_string()
-                    }
-                    None => match &content {
-                        Some(val) => {
-                            let len = std::cmp::min(250, val.len());
-                            val[0..len].to_string()
-                        }
-                        None => "[No description provided]".to_string(),
-                    },
+    let author = join_authors(
+        item.children()
+            .filter(|x| x.has_tag_name("author"))
+            .filter_map(|x| x.children().find(|x| x.has_tag_name("name")))
+            .filter_map(|x| x.text()),
+    );
+
+    Ok(Post {
+        id: 9999999,
+        title,
+        link,
+        date,
+        description,
+        content,
+        enclosure: None,
+        pid: publisher.pid.unwrap(),
+        publisher_name: Some(publisher.name.to_string()),
+        author,
+        guid,
+        updated,
+        revisions: None,
+        read: None,
+        starred: None,
+        snippet: None,
+    })
+}
+
+/// parse_rdf returns the posts in an rss 1.0 (rdf) feed, skipping over any malformed items.
+/// Unlike rss 2.0, the items are siblings of the channel rather than its children.
+fn parse_rdf(
+    doc: roxmltree::Document,
+    publisher: &Subscription,
+) -> Result<ParsedFeed, Box<dyn Error>> {
+    let mut feed = ParsedFeed::new();
+    let items = doc
+        .root_element()
+        .children()
+        .filter(|x| x.has_tag_name("item"));
+
+    for (index, item) in items.enumerate() {
+        feed.push(index, parse_rdf_item(item, publisher));
+    }
+    Ok(feed)
+}
+
+/// parse_rdf_item returns a post or an error string
+fn parse_rdf_item(item: Node, publisher: &Subscription) -> Result<Post, Box<dyn Error>> {
+    let nodes: Vec<Node> = item.descendants().collect();
+
+    // rdf:about uniquely identifies the item
+    let guid = item
+        .attribute((RDF_NS, "about"))
+        .map(|x| x.trim().to_owned());
+
+    // the rdf:about attribute is required to be the item's url, so use it if there's no link
+    let link = nodes
+        .iter()
+        .find(|x| x.has_tag_name("link"))
+        .and_then(|x| x.text())
+        .or(item.attribute((RDF_NS, "about")));
+
+    let link = match link {
+        Some(t) => t.trim().to_owned(),
+        None => {
+            return Err(
+                "Missing required field link, or it's in the incorrect format!"
+                    .to_string()
+                    .into(),
+            )
+        }
+    };
+
+    // dublin core dates are in the W3C (iso 8601) format rather than rfc 822
+    let date = nodes
+        .iter()
+        .find(|x| x.has_tag_name("date"))
+        .map(|x| x.text());
+
+    let date = match date {
+        Some(Some(text)) => match_w3c_date(text.trim()),
+        _ => Utc::now(),
+    };
+
+    let content = nodes
+        .iter()
+        .find(|x| x.has_tag_name("encoded"))
+        .map(|x| x.text());
+
+    let mut raw_content: Option<String> = None;
+    let content = match content {
+        Some(Some(c)) => {
+            let cleaned = web_scraper::clean_html(c, None);
+            raw_content = Some(cleaned.raw.to_string());
+            Some(cleaned.to_string())
+        }
+        _ => None,
+    };
+
+    let description = nodes
+        .iter()
+        .find(|x| x.has_tag_name("description"))
+        .map(|x| x.text());
+
+    let description = match description {
+        Some(Some(t)) => {
+            let cleaned = web_scraper::clean_html(t, None);
+            let text = cleaned.raw;
+            match text.as_str() {
+                "" => t.trim().to_string(),
+                _ => text,
+            }
+        }
+        _ => match raw_content {
+            Some(val) => val.chars().take(250).collect(),
+            None => "[No description provided]".to_string(),
+        },
+    };
+
+    let title = nodes
+        .iter()
+        .find(|x| x.has_tag_name("title"))
+        .map(|x| x.text());
+
+    let title = match title {
+        Some(Some(t)) => t.trim().to_owned(),
+        _ => {
+            let derived = match description.as_str() {
+                "[No description provided]" => None,
+                text => derive_title(text),
+            };
+            match derived {
+                Some(t) => t,
+                None => {
+                    return Err(
+                        "Missing required field title, or it's in the incorrect format!"
+                            .to_string()
+                            .into(),
+                    )
                 }
             }
-        };
+        }
+    };
 
-        let post = Post {
-            id: 9999999,
-            title,
-            link,
-            date,
-            description,
-            content,
-            enclosure: None,
-            pid: publisher.pid.unwrap(),
-            publisher_name: Some(publisher.name.to_string()),
-        };
-        vec.push(post);
+    let author = join_authors(
+        item.children()
+            .filter(|x| x.has_tag_name("creator"))
+            .filter_map(|x| x.text()),
+    );
+
+    Ok(Post {
+        id: 9999999,
+        title,
+        link,
+        date,
+        description,
+        content,
+        enclosure: None,
+        pid: publisher.pid.unwrap(),
+        publisher_name: Some(publisher.name.to_string()),
+        author,
+        guid,
+        updated: None,
+        revisions: None,
+        read: None,
+        starred: None,
+        snippet: None,
+    })
+}
+
+// JSON Feed (https://www.jsonfeed.org/version/1.1/) documents.
+// Only the fields we map onto a Post are deserialized.
+#[derive(Deserialize)]
+struct JsonFeed {
+    version: String,
+    title: Option<String>,
+    home_page_url: Option<String>,
+    #[serde(default)]
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Deserialize)]
+struct JsonFeedItem {
+    id: Option<serde_json::Value>,
+    url: Option<String>,
+    external_url: Option<String>,
+    title: Option<String>,
+    content_html: Option<String>,
+    content_text: Option<String>,
+    summary: Option<String>,
+    image: Option<String>,
+    date_published: Option<String>,
+    date_modified: Option<String>,
+    // version 1.1 uses authors, version 1.0 uses author
+    #[serde(default)]
+    authors: Vec<JsonFeedAuthor>,
+    author: Option<JsonFeedAuthor>,
+    #[serde(default)]
+    attachments: Vec<JsonFeedAttachment>,
+}
+
+#[derive(Deserialize)]
+struct JsonFeedAuthor {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonFeedAttachment {
+    url: String,
+    mime_type: Option<String>,
+}
+
+/// is_json_feed checks whether the data looks like a json document rather than xml
+fn is_json_feed(data: &str) -> bool {
+    data.trim_start().starts_with('{')
+}
+
+/// parse_json returns the posts in a JSON Feed document, skipping over any malformed items
+fn parse_json(data: &str, publisher: &Subscription) -> Result<ParsedFeed, Box<dyn Error>> {
+    let json: JsonFeed = serde_json::from_str(data)?;
+    if !json.version.starts_with("https://jsonfeed.org/version/") {
+        return Err("Unrecognized JSON feed version!".to_string().into());
+    }
+
+    let mut feed = ParsedFeed::new();
+    for (index, item) in json.items.into_iter().enumerate() {
+        feed.push(index, parse_json_item(item, publisher));
+    }
+    Ok(feed)
+}
+
+/// parse_json_item returns a post or an error string
+fn parse_json_item(item: JsonFeedItem, publisher: &Subscription) -> Result<Post, Box<dyn Error>> {
+    // the spec says ids should be strings, but some feeds use numbers
+    let guid = match &item.id {
+        Some(serde_json::Value::String(id)) => Some(id.to_owned()),
+        Some(serde_json::Value::Number(id)) => Some(id.to_string()),
+        _ => None,
+    };
+    // the id is only guaranteed to be unique, but many feeds use the permalink as the id
+    let id_link = guid.clone().filter(|id| id.starts_with("http"));
+    let link = match item.url.or(item.external_url).or(id_link) {
+        Some(link) => link,
+        None => {
+            return Err(
+                "Missing required field url, or it's in the incorrect format!"
+                    .to_string()
+                    .into(),
+            )
+        }
+    };
+
+    let mut raw_content: Option<String> = None;
+    let content = match (item.content_html, item.content_text) {
+        (Some(html), _) => {
+            let cleaned = web_scraper::clean_html(&html, None);
+            raw_content = Some(cleaned.raw.to_string());
+            Some(cleaned.to_string())
+        }
+        (None, Some(text)) => {
+            raw_content = Some(text.to_string());
+            Some(text)
+        }
+        (None, None) => None,
+    };
+
+    let description = match item.summary {
+        Some(summary) => summary,
+        None => match &raw_content {
+            Some(val) => val.chars().take(250).collect(),
+            None => "[No description provided]".to_string(),
+        },
+    };
+
+    // titles are optional in json feeds (e.g. micro posts), so fall back to the text
+    let title = match item.title {
+        Some(title) if !title.trim().is_empty() => title,
+        _ => match raw_content.as_deref().and_then(derive_title) {
+            Some(t) => t,
+            None => {
+                return Err(
+                    "Missing required field title, or it's in the incorrect format!"
+                        .to_string()
+                        .into(),
+                )
+            }
+        },
+    };
+
+    let updated = item
+        .date_modified
+        .as_ref()
+        .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+        .map(|d| d.to_utc());
+
+    let date = match item.date_published.or(item.date_modified) {
+        Some(d) => match DateTime::parse_from_rfc3339(&d) {
+            Ok(dt) => dt.to_utc(),
+            Err(_) => Utc::now(),
+        },
+        None => Utc::now(),
+    };
+
+    // prefer the item's main image, then an image attachment, then any attachment
+    let enclosure = item.image.or_else(|| {
+        let image = item.attachments.iter().find(|x| {
+            x.mime_type
+                .as_ref()
+                .is_some_and(|mime| mime.starts_with("image/"))
+        });
+        image.or(item.attachments.first()).map(|x| x.url.to_owned())
+    });
+
+    let authors: Vec<String> = item
+        .authors
+        .into_iter()
+        .chain(item.author)
+        .filter_map(|x| x.name)
+        .collect();
+    let author = match authors.is_empty() {
+        true => None,
+        false => Some(authors.join(", ")),
+    };
+
+    Ok(Post {
+        id: 9999999,
+        title,
+        link,
+        date,
+        description,
+        content,
+        enclosure,
+        pid: publisher.pid.unwrap(),
+        publisher_name: Some(publisher.name.to_string()),
+        author,
+        guid,
+        updated,
+        revisions: None,
+        read: None,
+        starred: None,
+        snippet: None,
+    })
+}
+
+/// normalize_url strips the tracking parameters and fragment from a link,
+/// so the same article shared with different campaign tags is treated as one post.
+/// Links that can't be parsed are returned as-is.
+pub fn normalize_url(link: &str) -> String {
+    let mut url = match url::Url::parse(link.trim()) {
+        Ok(val) => val,
+        Err(_) => return link.trim().to_string(),
+    };
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !is_tracking_param(key))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    if kept.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(kept);
     }
-    Ok(vec)
+    url.set_fragment(None);
+    url.to_string()
+}
+
+fn is_tracking_param(key: &str) -> bool {
+    key.starts_with("utm_")
+        || matches!(
+            key,
+            "fbclid" | "gclid" | "dclid" | "msclkid" | "mc_cid" | "mc_eid" | "_hsenc" | "_hsmi"
+        )
 }
 
 pub fn match_date(date: &str) -> DateTime<Utc> {
@@ -420,11 +1219,114 @@ pub fn match_date(date: &str) -> DateTime<Utc> {
     Utc::now()
 }
 
-// validate feed takes in a url pointing to an xml.
-// it returns the feed name
-pub async fn validate_feed(url: &str) -> Result<String, Box<dyn Error>> {
-    let val = reqwest::get(url).await?.text().await?;
-    let doc = roxmltree::Document::parse(&val)?;
+/// match_w3c_date parses the W3C profile of iso 8601 used by dublin core's dc:date.
+/// The seconds and even the time may be left out.
+pub fn match_w3c_date(date: &str) -> DateTime<Utc> {
+    if let Ok(val) = DateTime::parse_from_rfc3339(date) {
+        return val.to_utc();
+    }
+    // no seconds, e.g. 2024-03-01T10:00+09:00
+    if let Ok(val) = DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M%:z") {
+        return val.to_utc();
+    }
+    if let Ok(val) = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%MZ") {
+        return Utc.from_utc_datetime(&val);
+    }
+    if let Ok(val) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        return Utc.from_utc_datetime(&val.and_time(NaiveTime::MIN));
+    }
+    // if all else fails, return now
+    Utc::now()
+}
+
+/// FeedCandidate is a feed found while looking for the feeds of a web page
+#[derive(Debug, Serialize, Clone)]
+pub struct FeedCandidate {
+    pub url: String,
+    pub title: Option<String>,
+    // the feed's details, if it was already downloaded while looking for it
+    #[serde(skip)]
+    pub info: Option<FeedInfo>,
+}
+
+/// discover_feeds finds the feeds at a url, which can be a feed itself or a web page.
+/// Pages are searched for <link rel="alternate"> tags first,
+/// and if they don't have any the usual feed paths on the site are tried, all at once.
+pub async fn discover_feeds(url: &str) -> Result<Vec<FeedCandidate>, Box<dyn Error>> {
+    let res = DISCOVERY_CLIENT.get(url).send().await?.error_for_status()?;
+    // links on the page are relative to wherever we got redirected to
+    let base = res.url().clone();
+    let val = res.text().await?;
+    if let Ok(info) = feed_info(&val) {
+        return Ok(vec![FeedCandidate {
+            url: url.to_string(),
+            title: Some(info.title.clone()),
+            info: Some(info),
+        }]);
+    }
+
+    let found = web_scraper::find_feed_links(&val, base.as_str());
+    if !found.is_empty() {
+        return Ok(found);
+    }
+    let mut probes = Vec::new();
+    for path in FEED_PATHS {
+        probes.push(base.join(path)?.to_string());
+    }
+    // the errors are dropped as each probe finishes, dyn Error isn't Send
+    let infos =
+        future::join_all(probes.iter().map(|x| async { validate_feed(x).await.ok() })).await;
+    Ok(probes
+        .into_iter()
+        .zip(infos)
+        .filter_map(|(url, info)| {
+            let info = info?;
+            Some(FeedCandidate {
+                url,
+                title: Some(info.title.clone()),
+                info: Some(info),
+            })
+        })
+        .collect())
+}
+
+/// FeedInfo describes a feed we're able to read
+#[derive(Debug, Clone)]
+pub struct FeedInfo {
+    pub title: String,
+    // the website the feed belongs to, if the feed says
+    pub site_url: Option<String>,
+}
+
+// validate feed takes in a url pointing to an xml or json feed.
+// it returns the feed name and website
+pub async fn validate_feed(url: &str) -> Result<FeedInfo, Box<dyn Error>> {
+    let val = DISCOVERY_CLIENT
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    feed_info(&val)
+}
+
+// feed_info checks that the text is a feed we can read, returning its name and website
+fn feed_info(val: &str) -> Result<FeedInfo, Box<dyn Error>> {
+    if is_json_feed(val) {
+        let feed: JsonFeed = serde_json::from_str(val)?;
+        if !feed.version.starts_with("https://jsonfeed.org/version/") {
+            return Err("Unrecognized feed format!".to_string().into());
+        }
+        return match feed.title {
+            Some(title) => Ok(FeedInfo {
+                title,
+                site_url: feed.home_page_url,
+            }),
+            None => Err("Malformed feed format!".to_string().into()),
+        };
+    }
+    let doc = roxmltree::Document::parse(val)?;
 
     // look for version in rss
     let ver = doc
@@ -439,8 +1341,30 @@ pub async fn validate_feed(url: &str) -> Result<String, Box<dyn Error>> {
                 .descendants()
                 .find(|x| x.has_tag_name("title"))
                 .map(|x| x.text());
+            let channel = doc.descendants().find(|x| x.has_tag_name("channel"));
+            if let Some(Some(val)) = title {
+                Ok(FeedInfo {
+                    title: val.to_string(),
+                    site_url: channel.and_then(channel_link),
+                })
+            } else {
+                Err("Malformed feed format!".to_string().into())
+            }
+        }
+        _ if doc.root_element().has_tag_name("RDF") => {
+            // the channel's title comes before any of the items
+            let channel = doc
+                .root_element()
+                .children()
+                .find(|x| x.has_tag_name("channel"));
+            let title = &channel
+                .and_then(|x| x.children().find(|x| x.has_tag_name("title")))
+                .map(|x| x.text());
             if let Some(Some(val)) = title {
-                Ok(val.to_string())
+                Ok(FeedInfo {
+                    title: val.to_string(),
+                    site_url: channel.and_then(channel_link),
+                })
             } else {
                 Err("Malformed feed format!".to_string().into())
             }
@@ -449,14 +1373,26 @@ pub async fn validate_feed(url: &str) -> Result<String, Box<dyn Error>> {
             // it may be an atom feed, let's check.
             let feed = &doc.descendants().find(|x| x.has_tag_name("feed"));
             match feed {
-                Some(_) => {
+                Some(feed) => {
                     // get the feed title & return
                     let title = &doc
                         .descendants()
                         .find(|x| x.has_tag_name("title"))
                         .map(|x| x.text());
+                    // a link without a rel is an alternate link too
+                    let site_url = feed
+                        .children()
+                        .find(|x| {
+                            x.has_tag_name("link")
+                                && x.attribute("rel").unwrap_or("alternate") == "alternate"
+                        })
+                        .and_then(|x| x.attribute("href"))
+                        .map(|x| x.trim().to_string());
                     if let Some(Some(val)) = title {
-                        Ok(val.to_string())
+                        Ok(FeedInfo {
+                            title: val.to_string(),
+                            site_url,
+                        })
                     } else {
                         Err("Malformed feed format!".to_string().into())
                     }
@@ -467,6 +1403,17 @@ pub async fn validate_feed(url: &str) -> Result<String, Box<dyn Error>> {
     }
 }
 
+// channel_link gets the website from an rss channel,
+// skipping the atom:link some feeds add to point back at themselves
+fn channel_link(channel: Node) -> Option<String> {
+    channel
+        .children()
+        .find(|x| x.has_tag_name("link") && x.tag_name().namespace().is_none())
+        .and_then(|x| x.text())
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+}
+
 #[cfg(test)]
 mod rss_tests {
     use super::*;
@@ -483,15 +1430,18 @@ mod rss_tests {
             pid: Some(1),
             cid: 1,
             name: "nil".to_string(),
+            ..Default::default()
         };
         let res = parse_feed(&data, &obj).await;
         match res {
-            Ok(vec) => {
-                let first = &vec[0];
+            Ok(feed) => {
+                let first = &feed.posts[0];
                 assert_eq!(
                     first.title,
                     "The Best Dumb Stuff to Buy With Your Tax Refund Money"
                 );
+                // both of the item's dc:creators
+                assert_eq!(first.author, Some("Adam Rothbarth, HP".to_string()));
             }
             Err(error) => {
                 println!("{error}");
@@ -510,11 +1460,12 @@ mod rss_tests {
             pid: Some(1),
             cid: 1,
             name: "nil".to_string(),
+            ..Default::default()
         };
         let res = parse_feed(&data, &obj).await;
         match res {
-            Ok(vec) => {
-                let first = &vec[0];
+            Ok(feed) => {
+                let first = &feed.posts[0];
                 assert_eq!(first.title, "Giving the world a pluggable Gnutella");
                 assert_eq!(first.description, "WorldOS is a framework on which to build programs that work like Freenet or Gnutella -allowing distributed applications using peer-to-peer routing.");
             }
@@ -535,11 +1486,12 @@ mod rss_tests {
             pid: Some(1),
             cid: 1,
             name: "nil".to_string(),
+            ..Default::default()
         };
         let res = parse_feed(&data, &obj).await;
         match res {
-            Ok(vec) => {
-                let first = &vec[0];
+            Ok(feed) => {
+                let first = &feed.posts[0];
                 assert_eq!(first.title, "Cats and Dogs Form Unlikely Friendship");
                 assert_eq!(first.description, "In a heartwarming turn of events, a cat and a dog were spotted playing together in the park, proving that friendships can transcend species.");
             }
@@ -550,6 +1502,152 @@ mod rss_tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_rss_skips_bad_items() {
+        let data: String =
+            String::from_utf8_lossy(&fs::read("test-files/rss-20-bad-items.xml").unwrap())
+                .parse()
+                .unwrap();
+        let obj = Subscription {
+            url: "rss-20-bad-items.xml".to_string(),
+            pid: Some(1),
+            cid: 1,
+            name: "nil".to_string(),
+            ..Default::default()
+        };
+        let res = parse_feed(&data, &obj).await;
+        match res {
+            Ok(feed) => {
+                assert_eq!(feed.posts.len(), 4);
+                // title comes from the description
+                assert_eq!(
+                    feed.posts[1].title,
+                    "Coffee first, then code. Posting from the train this morning."
+                );
+                // link comes from the permalink guid
+                assert_eq!(
+                    feed.posts[2].link,
+                    "https://micro.example.com/2024/03/17/guid-only"
+                );
+                assert_eq!(feed.posts[3].title, "Another regular post");
+                // the item without a link is skipped and reported
+                assert_eq!(feed.skipped.len(), 1);
+                assert_eq!(feed.skipped[0].index, 3);
+                assert!(feed.report().unwrap().contains("link"));
+            }
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_identity() {
+        let data: String =
+            String::from_utf8_lossy(&fs::read("test-files/rss-20-bad-items.xml").unwrap())
+                .parse()
+                .unwrap();
+        let obj = Subscription {
+            url: "rss-20-bad-items.xml".to_string(),
+            pid: Some(1),
+            cid: 1,
+            name: "nil".to_string(),
+            ..Default::default()
+        };
+        let feed = parse_feed(&data, &obj).await.unwrap();
+        // the guid wins over the link
+        assert_eq!(
+            feed.posts[1].identity(),
+            "https://micro.example.com/2024/03/18/081200"
+        );
+        // no guid, so the link is used
+        assert_eq!(
+            feed.posts[3].identity(),
+            "https://micro.example.com/2024/03/another-post"
+        );
+    }
+
+    #[test]
+    fn test_feed_info() {
+        let info = |file: &str| match feed_info(&fs::read_to_string(file).unwrap()) {
+            Ok(val) => (val.title, val.site_url),
+            Err(error) => panic!("{error}"),
+        };
+        // the atom:link pointing back at the feed isn't the website
+        assert_eq!(
+            info("test-files/rss-20.xml"),
+            (
+                "VICE US".to_string(),
+                Some("https://www.vice.com/en%3Flocale%3Den_us".to_string())
+            )
+        );
+        assert_eq!(
+            info("test-files/atom.xml").1,
+            Some("https://www.theverge.com/".to_string())
+        );
+        assert_eq!(
+            info("test-files/feed.json").1,
+            Some("https://indienotes.example.org/".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(
+            normalize_url("https://example.com/a?utm_source=rss&utm_medium=feed&id=4#comments"),
+            "https://example.com/a?id=4"
+        );
+        assert_eq!(
+            normalize_url("https://example.com/a?fbclid=abc"),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            normalize_url("https://example.com/a?page=2&utm_campaign=x"),
+            normalize_url("https://example.com/a?page=2")
+        );
+        assert_eq!(normalize_url("not a url"), "not a url");
+    }
+
+    #[tokio::test]
+    async fn test_rss_1_0() {
+        let data: String = String::from_utf8_lossy(&fs::read("test-files/rss-10.xml").unwrap())
+            .parse()
+            .unwrap();
+        let obj = Subscription {
+            url: "rss-10.xml".to_string(),
+            pid: Some(1),
+            cid: 1,
+            name: "nil".to_string(),
+            ..Default::default()
+        };
+        let res = parse_feed(&data, &obj).await;
+        match res {
+            Ok(feed) => {
+                assert_eq!(feed.posts.len(), 3);
+                let first = &feed.posts[0];
+                assert_eq!(first.title, "Linux Kernel 6.8 Released");
+                assert_eq!(first.author, Some("editor".to_string()));
+                assert_eq!(
+                    first.date,
+                    Utc.with_ymd_and_hms(2024, 3, 19, 23, 15, 0).unwrap()
+                );
+                assert!(first
+                    .get_content()
+                    .contains("<p>Read the full changelog for details.</p>"));
+                let second = &feed.posts[1];
+                assert_eq!(
+                    second.date,
+                    Utc.with_ymd_and_hms(2024, 3, 19, 14, 0, 0).unwrap()
+                );
+                // falls back to rdf:about when there's no link
+                let third = &feed.posts[2];
+                assert_eq!(
+                    third.link,
+                    "https://news.example.jp/story/24/03/19/1200/no-link-element"
+                );
+            }
+            Err(error) => panic!("{error}"),
+        }
+    }
+
     #[tokio::test]
     async fn test_atom() {
         let data: String = String::from_utf8_lossy(&fs::read("test-files/atom.xml").unwrap())
@@ -560,13 +1658,15 @@ mod rss_tests {
             pid: Some(1),
             cid: 1,
             name: "nil".to_string(),
+            ..Default::default()
         };
         let res = parse_feed(&data, &obj).await;
         match res {
-            Ok(vec) => {
-                let first = &vec[0];
+            Ok(feed) => {
+                let first = &feed.posts[0];
                 assert_eq!(first.title, "Google’s use of student data could effectively ban Chromebooks from Denmark schools");
                 assert_eq!(first.link, "https://www.theverge.com/2024/2/7/24065332/denmark-google-student-data-collection-privacy");
+                assert_eq!(first.author, Some("Wes Davis".to_string()));
             }
             Err(error) => {
                 println!("{error}");
@@ -575,6 +1675,301 @@ mod rss_tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_json_feed() {
+        let data: String = String::from_utf8_lossy(&fs::read("test-files/feed.json").unwrap())
+            .parse()
+            .unwrap();
+        let obj = Subscription {
+            url: "feed.json".to_string(),
+            pid: Some(1),
+            cid: 1,
+            name: "nil".to_string(),
+            ..Default::default()
+        };
+        let res = parse_feed(&data, &obj).await;
+        match res {
+            Ok(feed) => {
+                assert_eq!(feed.posts.len(), 3);
+                let first = &feed.posts[0];
+                assert_eq!(first.title, "Why I went back to a static site");
+                assert_eq!(
+                    first.description,
+                    "Three years on a hosted platform, and why I left."
+                );
+                assert_eq!(first.author, Some("Sam Rivera".to_string()));
+                assert!(first
+                    .get_content()
+                    .contains("<p>Here is what I learned.</p>"));
+                // title-less micro posts get their title from the text
+                let second = &feed.posts[1];
+                assert_eq!(
+                    second.title,
+                    "Just set up webmentions on the blog. Replies welcome!"
+                );
+                // image attachments are preferred for the enclosure
+                let third = &feed.posts[2];
+                assert_eq!(
+                    third.enclosure,
+                    Some("https://indienotes.example.org/img/episode-12.jpg".to_string())
+                );
+            }
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    // serves atom.xml locally with an etag, answering 304 when the client already has it
+    async fn serve_atom_with_etag() -> String {
+        use axum::{
+            http::{header, HeaderMap, StatusCode},
+            response::IntoResponse,
+            routing::get,
+            Router,
+        };
+
+        async fn atom(headers: HeaderMap) -> impl IntoResponse {
+            if headers
+                .get(header::IF_NONE_MATCH)
+                .is_some_and(|x| x == "\"v1\"")
+            {
+                return StatusCode::NOT_MODIFIED.into_response();
+            }
+            let body = fs::read_to_string("test-files/atom.xml").unwrap();
+            ([(header::ETAG, "\"v1\"")], body).into_response()
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/atom.xml", get(atom));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}/atom.xml")
+    }
+
+    #[tokio::test]
+    async fn test_conditional_fetch() {
+        let mut obj = Subscription {
+            url: serve_atom_with_etag().await,
+            pid: Some(1),
+            cid: 1,
+            name: "nil".to_string(),
+            ..Default::default()
+        };
+        let feed = get_whole_feed(vec![obj.clone()]).await;
+        assert!(!feed.posts.is_empty());
+        assert_eq!(feed.fetched[0].etag, Some("\"v1\"".to_string()));
+
+        // sending the etag back means there's nothing new to parse
+        obj.etag = feed.fetched[0].etag.clone();
+        let feed = get_whole_feed(vec![obj]).await;
+        assert_eq!(feed.posts.len(), 0);
+        assert_eq!(feed.fetched.len(), 1);
+        assert_eq!(feed.fetched[0].etag, Some("\"v1\"".to_string()));
+    }
+
+    // serves atom.xml behind a few permanent and temporary redirects
+    async fn serve_redirects() -> String {
+        use axum::{response::Redirect, routing::get, Router};
+
+        let app = Router::new()
+            .route(
+                "/atom.xml",
+                get(|| async { fs::read_to_string("test-files/atom.xml").unwrap() }),
+            )
+            .route(
+                "/moved",
+                get(|| async { Redirect::permanent("/moved-again") }),
+            )
+            .route(
+                "/moved-again",
+                get(|| async { Redirect::permanent("/atom.xml") }),
+            )
+            .route(
+                "/temporary",
+                get(|| async { Redirect::temporary("/atom.xml") }),
+            )
+            .route(
+                "/moved-to-temporary",
+                get(|| async { Redirect::permanent("/temporary") }),
+            )
+            .route("/loop", get(|| async { Redirect::permanent("/loop") }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_fetch_redirects() {
+        let base = serve_redirects().await;
+        let sub = |path: &str| Subscription {
+            url: format!("{base}{path}"),
+            pid: Some(1),
+            cid: 1,
+            name: "nil".to_string(),
+            ..Default::default()
+        };
+
+        // every hop was permanent, so the feed has moved to the end of the chain
+        let feed = get_whole_feed(vec![sub("/moved")]).await;
+        assert!(!feed.posts.is_empty());
+        assert_eq!(feed.fetched[0].moved_to, Some(format!("{base}/atom.xml")));
+
+        // temporary redirects are followed but not remembered
+        let feed = get_whole_feed(vec![sub("/temporary")]).await;
+        assert!(!feed.posts.is_empty());
+        assert_eq!(feed.fetched[0].moved_to, None);
+
+        // the feed has only moved as far as the temporary redirect
+        let feed = get_whole_feed(vec![sub("/moved-to-temporary")]).await;
+        assert!(!feed.posts.is_empty());
+        assert_eq!(feed.fetched[0].moved_to, Some(format!("{base}/temporary")));
+
+        let feed = get_whole_feed(vec![sub("/loop")]).await;
+        assert!(feed.posts.is_empty());
+        assert!(feed.fetched[0].error.is_some());
+        assert_eq!(feed.fetched[0].moved_to, None);
+    }
+
+    // serves a small site with a few feeds and pages that do and don't link to them
+    async fn serve_site() -> String {
+        use axum::{http::header, routing::get, Router};
+
+        fn page(links: &str) -> ([(header::HeaderName, &'static str); 1], String) {
+            let html = format!("<html><head><title>Site</title>{links}</head><body></body></html>");
+            ([(header::CONTENT_TYPE, "text/html")], html)
+        }
+
+        let app = Router::new()
+            .route("/atom.xml", get(|| async { fs::read_to_string("test-files/atom.xml").unwrap() }))
+            .route("/rss.xml", get(|| async { fs::read_to_string("test-files/rss-20.xml").unwrap() }))
+            .route("/feed.json", get(|| async { fs::read_to_string("test-files/feed.json").unwrap() }))
+            .route(
+                "/blog/",
+                get(|| async {
+                    page(r#"<link rel="alternate" type="application/atom+xml" title="Blog" href="../atom.xml">"#)
+                }),
+            )
+            .route(
+                "/both",
+                get(|| async {
+                    page(
+                        r#"<link rel="alternate" type="application/atom+xml" href="/atom.xml">
+                        <link rel="alternate" type="application/feed+json" href="/feed.json">"#,
+                    )
+                }),
+            )
+            .route("/plain", get(|| async { page("") }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_discover_feeds() {
+        let base = serve_site().await;
+        let discover = |path: &str| {
+            let url = format!("{base}{path}");
+            async move {
+                match discover_feeds(&url).await {
+                    Ok(val) => val.into_iter().map(|x| x.url).collect::<Vec<String>>(),
+                    Err(error) => panic!("{error}"),
+                }
+            }
+        };
+
+        // a feed is its own feed
+        assert_eq!(discover("/rss.xml").await, vec![format!("{base}/rss.xml")]);
+        assert_eq!(discover("/blog/").await, vec![format!("{base}/atom.xml")]);
+        assert_eq!(
+            discover("/both").await,
+            vec![format!("{base}/atom.xml"), format!("{base}/feed.json")]
+        );
+        // nothing linked, so the usual places are tried. /feed doesn't exist here
+        assert_eq!(
+            discover("/plain").await,
+            vec![format!("{base}/rss.xml"), format!("{base}/atom.xml")]
+        );
+    }
+
+    // serves a feed that takes a while to answer, recording the most requests it had in flight at once
+    async fn serve_slow_feed(delay: Duration) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use axum::{routing::get, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let max = Arc::clone(&max_in_flight);
+        let app = Router::new().route(
+            "/atom.xml",
+            get(move || {
+                let in_flight = Arc::clone(&in_flight);
+                let max = Arc::clone(&max);
+                async move {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(delay).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    fs::read_to_string("test-files/atom.xml").unwrap()
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}/atom.xml"), max_in_flight)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_limits_per_host() {
+        let (url, max_in_flight) = serve_slow_feed(Duration::from_millis(100)).await;
+        let subs = (1..=6)
+            .map(|pid| Subscription {
+                url: url.to_string(),
+                pid: Some(pid),
+                cid: 1,
+                name: "nil".to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let config = FetchConfig {
+            max_concurrent: 10,
+            max_per_host: 2,
+            host_delay: Duration::from_millis(0),
+            timeout: Duration::from_secs(5),
+        };
+        let mut rx = fetch_feeds(subs, config);
+        let mut count = 0;
+        while let Some(feed) = rx.recv().await {
+            assert!(feed.result.error.is_none());
+            assert!(!feed.posts.is_empty());
+            count += 1;
+        }
+        assert_eq!(count, 6);
+        assert_eq!(max_in_flight.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_timeout() {
+        let (url, _) = serve_slow_feed(Duration::from_secs(2)).await;
+        let sub = Subscription {
+            url,
+            pid: Some(1),
+            cid: 1,
+            name: "nil".to_string(),
+            ..Default::default()
+        };
+        let config = FetchConfig {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut rx = fetch_feeds(vec![sub], config);
+        let feed = rx.recv().await.unwrap();
+        assert!(feed.result.error.is_some());
+        assert!(feed.posts.is_empty());
+        assert!(rx.recv().await.is_none());
+    }
+
     #[tokio::test]
     async fn test_get_url_works() {
         // a url pointing to the raw data of the atom.xml file hosted on github
@@ -596,9 +1991,10 @@ mod rss_tests {
             pid: Some(1),
             cid: 1,
             name: "nil".to_string(),
+            ..Default::default()
         };
         let urls: Vec<Subscription> = vec![obj];
-        let posts = get_whole_feed(urls).await;
+        let posts = get_whole_feed(urls).await.posts;
         let post = posts.iter().find(|x| x.title == "Google’s use of student data could effectively ban Chromebooks from Denmark schools");
         // should not error if all is good
         let _post = post.unwrap();
@@ -0,0 +1,271 @@
+use crate::escape_xml;
+use chrono::{DateTime, NaiveDate, Utc};
+use scraper::Html;
+
+// how much text a snippet shows, in characters
+const SNIPPET_LENGTH: usize = 200;
+// how much of the text before the first match a snippet keeps
+const SNIPPET_LEAD: usize = 60;
+
+/// SearchQuery is a parsed search. Words are all required,
+/// "quoted phrases" have to appear as written, and -word or -"phrase" leaves out posts that have them.
+pub struct SearchQuery {
+    pub terms: Vec<SearchTerm>,
+}
+
+/// SearchFilters narrow a search down to a publisher, a channel or a range of dates
+#[derive(Default)]
+pub struct SearchFilters {
+    pub pid: Option<u64>,
+    pub cid: Option<u64>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+pub struct SearchTerm {
+    pub text: String,
+    pub phrase: bool,
+    pub exclude: bool,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Self {
+        let mut terms = vec![];
+        let mut chars = query.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            let exclude = c == '-';
+            if exclude {
+                chars.next();
+            }
+
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                let phrase: String = chars.by_ref().take_while(|x| *x != '"').collect();
+                let phrase = words(&phrase).join(" ");
+                if !phrase.is_empty() {
+                    terms.push(SearchTerm {
+                        text: phrase,
+                        phrase: true,
+                        exclude,
+                    });
+                }
+            } else {
+                let word: String = chars.by_ref().take_while(|x| !x.is_whitespace()).collect();
+                // mysql splits words on punctuation anyway, so self-driving is two words
+                for text in words(&word) {
+                    terms.push(SearchTerm {
+                        text,
+                        phrase: false,
+                        exclude,
+                    });
+                }
+            }
+        }
+        SearchQuery { terms }
+    }
+
+    /// is_empty is true when there's nothing to look for, only things to leave out
+    pub fn is_empty(&self) -> bool {
+        self.terms.iter().all(|x| x.exclude)
+    }
+
+    /// boolean_mode writes the query for MATCH ... AGAINST (... IN BOOLEAN MODE)
+    pub fn boolean_mode(&self) -> String {
+        self.terms
+            .iter()
+            .map(|x| {
+                let op = if x.exclude { '-' } else { '+' };
+                if x.phrase {
+                    format!("{op}\"{}\"", x.text)
+                } else {
+                    format!("{op}{}", x.text)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// snippet takes the part of the text around the first match, with every match wrapped in <mark>.
+    /// The text can be html, only its text is kept and the snippet is escaped to be shown as html.
+    pub fn snippet(&self, text: &str) -> String {
+        let text = Html::parse_fragment(text)
+            .root_element()
+            .text()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let text: Vec<char> = text
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .chars()
+            .collect();
+        // lowercased a character at a time so positions still line up with the text
+        let lower: Vec<char> = text
+            .iter()
+            .map(|c| c.to_lowercase().next().unwrap_or(*c))
+            .collect();
+        let needles: Vec<Vec<char>> = self
+            .terms
+            .iter()
+            .filter(|x| !x.exclude)
+            .map(|x| x.text.to_lowercase().chars().collect())
+            .collect();
+
+        let matches = find_matches(&lower, &needles);
+        let start = match matches.first() {
+            Some((at, _)) => at.saturating_sub(SNIPPET_LEAD),
+            None => 0,
+        };
+        let end = (start + SNIPPET_LENGTH).min(text.len());
+
+        let mut out = String::new();
+        if start > 0 {
+            out.push('…');
+        }
+        let mut at = start;
+        for (from, to) in matches.into_iter().filter(|(from, _)| *from >= start) {
+            let to = to.min(end);
+            if from >= end {
+                break;
+            }
+            out.push_str(&escape_xml(&text[at..from].iter().collect::<String>()));
+            out.push_str("<mark>");
+            out.push_str(&escape_xml(&text[from..to].iter().collect::<String>()));
+            out.push_str("</mark>");
+            at = to;
+        }
+        out.push_str(&escape_xml(&text[at..end].iter().collect::<String>()));
+        if end < text.len() {
+            out.push('…');
+        }
+        out
+    }
+}
+
+/// parse_date reads a date range bound, either a full rfc3339 time or just a date.
+/// A date on its own is the start of the day, or the end of it when end is true,
+/// so a range from 2024-03-01 to 2024-03-01 covers the whole day.
+pub fn parse_date(text: &str, end: bool) -> Option<DateTime<Utc>> {
+    if let Ok(val) = DateTime::parse_from_rfc3339(text) {
+        return Some(val.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+    let time = if end {
+        date.and_hms_opt(23, 59, 59)
+    } else {
+        date.and_hms_opt(0, 0, 0)
+    };
+    time.map(|x| x.and_utc())
+}
+
+// words splits text into the words mysql would index, dropping its boolean operators
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'' && c != '_')
+        .map(|x| x.trim_matches('\''))
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+// find_matches returns where each needle appears in the text, in order and without overlaps
+fn find_matches(text: &[char], needles: &[Vec<char>]) -> Vec<(usize, usize)> {
+    let mut found = vec![];
+    let mut i = 0;
+    while i < text.len() {
+        // the longest needle wins, so a phrase isn't cut short by one of its words
+        let longest = needles
+            .iter()
+            .filter(|x| !x.is_empty() && text[i..].starts_with(x))
+            .map(|x| x.len())
+            .max();
+        match longest {
+            Some(len) => {
+                found.push((i, i + len));
+                i += len;
+            }
+            None => i += 1,
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod search_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        let query =
+            SearchQuery::parse(r#"waymo "self driving car" -tesla self-driving -"elon musk" +(x)"#);
+        let terms: Vec<(&str, bool, bool)> = query
+            .terms
+            .iter()
+            .map(|x| (x.text.as_str(), x.phrase, x.exclude))
+            .collect();
+        assert_eq!(
+            terms,
+            vec![
+                ("waymo", false, false),
+                ("self driving car", true, false),
+                ("tesla", false, true),
+                ("self", false, false),
+                ("driving", false, false),
+                ("elon musk", true, true),
+                ("x", false, false),
+            ]
+        );
+        assert_eq!(
+            query.boolean_mode(),
+            r#"+waymo +"self driving car" -tesla +self +driving -"elon musk" +x"#
+        );
+        assert!(!query.is_empty());
+        assert!(SearchQuery::parse("-tesla").is_empty());
+        assert!(SearchQuery::parse(r#"  "" () "#).is_empty());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let day = |x: &str, end| parse_date(x, end).map(|x| x.to_rfc3339());
+        assert_eq!(
+            day("2024-03-01", false),
+            Some("2024-03-01T00:00:00+00:00".to_string())
+        );
+        assert_eq!(
+            day("2024-03-01", true),
+            Some("2024-03-01T23:59:59+00:00".to_string())
+        );
+        assert_eq!(
+            day("2024-03-01T10:00:00+08:00", true),
+            Some("2024-03-01T02:00:00+00:00".to_string())
+        );
+        assert_eq!(day("last month", false), None);
+    }
+
+    #[test]
+    fn test_snippet() {
+        let query = SearchQuery::parse(r#"waymo "driverless taxi""#);
+        let text = "<p>A person jumped on the hood of a <b>Waymo</b> driverless taxi & smashed its windshield.</p>";
+        assert_eq!(
+            query.snippet(text),
+            "A person jumped on the hood of a <mark>Waymo</mark> <mark>driverless taxi</mark> &amp; smashed its windshield."
+        );
+
+        // long text is cut down to the part around the first match
+        let long = format!("{} Waymo {}", "before ".repeat(50), "after ".repeat(50));
+        let snippet = query.snippet(&long);
+        assert!(snippet.starts_with('…'));
+        assert!(snippet.ends_with('…'));
+        assert!(snippet.contains("<mark>Waymo</mark>"));
+        assert_eq!(
+            snippet.chars().count(),
+            SNIPPET_LENGTH + 2 + "<mark></mark>".len()
+        );
+
+        // nothing to highlight, so it's just the start of the text
+        assert_eq!(query.snippet("no match here"), "no match here");
+    }
+}
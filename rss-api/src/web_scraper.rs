@@ -1,5 +1,8 @@
 use crate::Post;
-use crate::{logger::DetailedError, rss_parser::from_url};
+use crate::{
+    logger::DetailedError,
+    rss_parser::{from_url, FeedCandidate},
+};
 use lazy_static::lazy_static;
 use scraper::{ElementRef, Html, Selector};
 use std::error::Error;
@@ -79,6 +82,55 @@ pub async fn scrape(post: &mut Post) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// the types a <link rel="alternate"> has when it points to a feed
+const FEED_TYPES: [&str; 3] = [
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+];
+
+/// find_feed_links reads the feeds a page advertises with <link rel="alternate"> tags.
+/// Relative links are resolved against base, the url the page came from.
+pub fn find_feed_links(data: &str, base: &str) -> Vec<FeedCandidate> {
+    let doc = Html::parse_document(data);
+    let selector = Selector::parse("link[rel][type][href]").unwrap();
+    let base = url::Url::parse(base).ok();
+    let mut found: Vec<FeedCandidate> = vec![];
+
+    for link in doc.select(&selector) {
+        let ele = link.value();
+        let alternate = ele
+            .attr("rel")
+            .unwrap_or_default()
+            .split_whitespace()
+            .any(|x| x.eq_ignore_ascii_case("alternate"));
+        // the type can come with parameters, e.g. application/rss+xml; charset=utf-8
+        let kind = ele.attr("type").unwrap_or_default().split(';').next();
+        let is_feed =
+            kind.is_some_and(|x| FEED_TYPES.iter().any(|t| t.eq_ignore_ascii_case(x.trim())));
+        if !alternate || !is_feed {
+            continue;
+        }
+
+        let href = ele.attr("href").unwrap_or_default().trim();
+        let url = match &base {
+            Some(base) => base.join(href),
+            None => url::Url::parse(href),
+        };
+        if let Ok(url) = url {
+            let url = url.to_string();
+            if !found.iter().any(|x| x.url == url) {
+                found.push(FeedCandidate {
+                    url,
+                    title: ele.attr("title").map(|x| x.trim().to_owned()),
+                    info: None,
+                });
+            }
+        }
+    }
+    found
+}
+
 pub fn clean_html(data: &str, selector: Option<&Selector>) -> CleanedHTML {
     let doc = Html::parse_document(data);
     let mut builder = String::new();
@@ -222,6 +274,26 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_find_feed_links() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" type="text/css" href="/style.css">
+            <link rel="alternate" type="application/rss+xml" title="Posts" href="/feed.xml">
+            <link rel="alternate" type="application/atom+xml; charset=utf-8" href="https://cdn.example.com/atom.xml">
+            <link rel="alternate" type="application/feed+json" title="Posts (JSON)" href="feed.json">
+            <link rel="alternate" type="application/rss+xml" title="Posts again" href="/feed.xml">
+            <link rel="alternate" hreflang="fr" type="text/html" href="/fr/">
+        </head><body></body></html>"#;
+        let found = find_feed_links(html, "https://example.com/blog/");
+
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].url, "https://example.com/feed.xml");
+        assert_eq!(found[0].title, Some("Posts".to_string()));
+        assert_eq!(found[1].url, "https://cdn.example.com/atom.xml");
+        assert_eq!(found[1].title, None);
+        assert_eq!(found[2].url, "https://example.com/blog/feed.json");
+    }
+
     #[test]
     fn test_clean_html() {
         let html = r#" <figure> <img alt="Samsung Galaxy S24 Ultra" src="https://cdn.vox-cdn.com/thumbor/yfM4CVWVjzjMZlkhIWwWX01FMrc=/0x0:2000x1333/1310x873/cdn.vox-cdn.com/uploads/chorus_image/image/73226505/DSC06482.0.jpg" /> <figcaption><em>The stinky stylus in question.</em> | Photo by Allison Johnson / The Verge</figcaption> </figure> <p id="ihJ4FM">I smelled the S Pen, and the reports are true: it kind of stinks. </p> <p id="HdqSFq">The S Pen is one of the <a href="https://www.theverge.com/24053907/samsung-galaxy-s24-ultra-review-ai-screen-camera-battery">Samsung Galaxy S24 Ultra’s</a> signature features — it’s a stylus that lives in the phone. A report from Reddit user LatifYil <a href="https://www.reddit.com/r/samsung/comments/1bixq94/why_does_my_s_pen_smell_so_bad/">kicked off the S Pen aroma discussion</a> earlier this week, noting that the S Pen on their Samsung Galaxy S24 Ultra “absolutely reeks.” Dozens of commenters with S24 Ultras (and earlier stylus-toting Galaxy phones) responded in affirmation: their styli stank.</p> <p id="WclJas"><a href="https://www.sammobile.com/news/galaxy-s24-ultra-s-pen-smells-burnt-plastic/">As noted by <em>SamMobile</em></a>, a moderator on Samsung’s EU community forums <a href="https://eu.community.samsung.com/t5/galaxy-s24-series/spen-tip-smells-burnt/td-p/9309704">offered a reasonable explanation</a> for the smell:</p> <blockquote><p id="W7ic9L">This isn’t anything to be concerned about. While the S Pen is in its holster, it is close to the internal components of the phone, which will generate heat while...</p></blockquote> <p> <a href="https://www.theverge.com/2024/3/22/24108848/samsung-galaxy-s24-ultra-s-pen-stylus-smell">Continue reading&hellip;</a> </p> "#;
@@ -4,33 +4,95 @@ const API_URL = import.meta.env.VITE_IS_DOCKER_COMPOSED!=null?
 import.meta.env.VITE_IS_DOCKER_COMPOSED :
 "http://localhost:3000/"
 
-export async function get_channels() {
+// login and register share everything but the endpoint
+async function authenticate(endpoint, username, password) {
+    const store = useUserStore();
+    const url = API_URL + endpoint;
+    const response = await fetch(url, {
+        credentials: "include",
+        method: "POST",
+        headers: {
+            "Content-Type": "application/json",
+        },
+        body: JSON.stringify(
+            {
+                username: username,
+                password: password,
+            }
+        )
+    });
+    if (!response.ok){
+        throw new Error(await response.text())
+    }
+    const me = await response.json();
+    store.uid = me.uid;
+    store.username = me.username;
+}
+
+export async function login(username, password) {
+    return authenticate("login", username, password);
+}
+
+export async function register(username, password) {
+    return authenticate("register", username, password);
+}
+
+export async function logout() {
+    const store = useUserStore();
+    const url = API_URL + "logout";
+    await fetch(url, { method: "POST", credentials: "include" });
+    store.uid = null;
+    store.username = null;
+}
+
+// get_me checks the session cookie is still good, returning false if it isn't
+export async function get_me() {
     const store = useUserStore();
-    const url = API_URL + "channel?uid=" + store.uid;
-    const response = await fetch(url);
+    const url = API_URL + "me";
+    const response = await fetch(url, { credentials: "include" });
+    if (!response.ok){
+        return false;
+    }
+    const me = await response.json();
+    store.uid = me.uid;
+    store.username = me.username;
+    return true;
+}
+
+export async function get_channels() {
+    const url = API_URL + "channel";
+    const response = await fetch(url, { credentials: "include" });
     const channels = await response.json();
     return channels;
 }
 
 export async function get_posts(id) {
     const url = API_URL + "feed?cid=" + id;
-    const response = await fetch(url);
+    const response = await fetch(url, { credentials: "include" });
     const posts = await response.json();
     return posts;
 }
 
+// watch_posts calls on_post with each new post in the channel as the server stores it.
+// The browser reconnects by itself, picking up after the last post it got. Close the returned source to stop.
+export function watch_posts(cid, on_post) {
+    const url = API_URL + "events?cid=" + cid;
+    const source = new EventSource(url, { withCredentials: true });
+    source.addEventListener("post", (e) => on_post(JSON.parse(e.data)));
+    return source;
+}
 
-export async function get_article(get_url, to_scrape) {
+export async function get_article(id, to_scrape) {
     const url = API_URL + "read";
     const response = await fetch(url, {
+        credentials: "include",
         method: "POST",
         headers: {
             "Content-Type": "application/json",
         },
         body: JSON.stringify(
             {
-                id: null,
-                url: get_url,
+                id: id,
                 scrape: to_scrape,
             }
         )
@@ -40,41 +102,94 @@ export async function get_article(get_url, to_scrape) {
 }
 
 
+export async function mark_read(cid, before){
+    const url = API_URL + "read/mark";
+    const response = await fetch(url, {
+        credentials: "include",
+        method: "POST",
+        headers: {
+            "Content-Type": "application/json",
+        },
+        body: JSON.stringify(
+            {
+                cid: cid,
+                before: before,
+            }
+        )
+    });
+    if (!response.ok){
+        throw new Error(await response.text())
+    }
+}
+
+export async function star_post(id){
+    const url = API_URL + "star";
+    const response = await fetch(url, {
+        credentials: "include",
+        method: "POST",
+        headers: {
+            "Content-Type": "application/json",
+        },
+        body: JSON.stringify(
+            {
+                id: id,
+            }
+        )
+    });
+    if (!response.ok){
+        throw new Error(await response.text())
+    }
+}
+
+export async function unstar_post(id){
+    const url = API_URL + "star?id=" + id;
+    const response = await fetch(url, { method: "DELETE", credentials: "include" });
+    if (!response.ok){
+        throw new Error(await response.text())
+    }
+}
+
+export async function get_starred(offset){
+    const url = API_URL + "starred?offset=" + offset;
+    const response = await fetch(url, { credentials: "include" });
+    const posts = await response.json();
+    return posts
+}
+
 export async function get_all(offset){
-    const store = useUserStore();
-    const url = API_URL + "all?uid=" + store.uid + "&offset=" + offset;
-    const response = await fetch(url);
+    const url = API_URL + "all?offset=" + offset;
+    const response = await fetch(url, { credentials: "include" });
     const posts = await response.json();
     return posts
 }
 
 export async function get_subscriptions(id){
     const url = API_URL + "sub?cid=" + id;
-    const response = await fetch(url);
+    const response = await fetch(url, { credentials: "include" });
     const subs = await response.json();
     return subs
 }
 
 export async function get_subscriptions_for_user(){
-    const store = useUserStore();
-    const url = API_URL + "sub?uid=" + store.uid;
-    const response = await fetch(url);
+    const url = API_URL + "sub";
+    const response = await fetch(url, { credentials: "include" });
     const subs = await response.json();
     return subs
 }
 
-export async function create_channel(name){
-    const store = useUserStore();
+// query is optional, passing one creates a smart channel
+export async function create_channel(name, query = null){
     const url = API_URL + "channel";
     const response = await fetch(url, {
+        credentials: "include",
         method: "POST",
         headers: {
             "Content-Type": "Application/json",
         },
         body: JSON.stringify(
             {
-                uid: store.uid,
-                name: name
+                name: name,
+                query: query
             }
         )
     });
@@ -85,8 +200,8 @@ export async function create_subscription(cid, url_to_subscribe){
     cid = Number(cid);
     const url = API_URL + "sub";
 
-    return fetch(url, 
-    {
+    return fetch(url, {
+        credentials: "include",
         method: "POST",
         headers: {
             "Content-Type": "Application/json",
@@ -100,6 +215,10 @@ export async function create_subscription(cid, url_to_subscribe){
         )
     })
     .then((resp) => {
+        // the url had more than one feed, hand them back to choose from
+        if (resp.status == 300){
+            return resp.json()
+        }
         if (!resp.ok){
             return resp.text().then((text) => {throw new Error(text)})
         }
@@ -111,7 +230,8 @@ export async function create_subscription(cid, url_to_subscribe){
 
 export async function delete_subscription(cid, pid){
     const url = API_URL + "sub";
-    const response = await fetch(url,{
+    const response = await fetch(url, {
+        credentials: "include",
         method: "DELETE",
         headers:{
             "Content-Type": "Application/json",
@@ -125,10 +245,30 @@ export async function delete_subscription(cid, pid){
     });
 }
 
+export async function enable_subscription(pid){
+    const url = API_URL + "sub/enable";
+    return fetch(url, {
+        credentials: "include",
+        method: "POST",
+        headers:{
+            "Content-Type": "Application/json",
+        },
+        body: JSON.stringify(
+            {
+                pid: Number(pid)
+            }
+        )
+    }).then((resp) => {
+        if (!resp.ok){
+            return resp.text().then((text) => {throw new Error(text)})
+        }
+    })
+}
+
 export async function delete_channel(cid){
-    const store = useUserStore();
-    const url = API_URL + "channel?uid=" + store.uid +"&cid="+cid;
-    return fetch(url,{
+    const url = API_URL + "channel?cid="+cid;
+    return fetch(url, {
+        credentials: "include",
         method: "DELETE"
     }).then((resp) => {
         if (!resp.ok){
@@ -3,9 +3,12 @@ import { ref } from 'vue'
 import Today from '@/views/Today.vue'
 import Article from '@/views/Article.vue'
 import All from '@/views/All.vue'
+import Starred from '@/views/Starred.vue'
 import Settings from '@/views/Settings.vue'
 import ChannelSettings from '@/views/ChannelSettings.vue'
-import { useScrollStore } from "@/stores/state.js";
+import Login from '@/views/Login.vue'
+import { useScrollStore, useUserStore } from "@/stores/state.js";
+import { get_me } from "@/lib.js";
 
 const router = createRouter({
   history: createWebHistory(import.meta.env.BASE_URL),
@@ -16,7 +19,7 @@ const router = createRouter({
       component: Today
     },
     {
-      path: '/article/:url',
+      path: '/article/:id',
       name: 'article',
       component: Article,
       props: route => ({query:route.query.pid})
@@ -26,6 +29,11 @@ const router = createRouter({
       name: 'all',
       component: All
     },
+    {
+      path: '/starred',
+      name: 'starred',
+      component: Starred
+    },
     {
       path: '/settings',
       name: 'settings',
@@ -36,6 +44,11 @@ const router = createRouter({
       name: 'channel',
       component: ChannelSettings
     },
+    {
+      path: '/login',
+      name: 'login',
+      component: Login
+    },
   ],
   scrollBehavior (to, from, savedPosition) {
     let store = useScrollStore()
@@ -48,4 +61,15 @@ const router = createRouter({
   }
 })
 
+// everything but the login page needs a session, checked with the api the first time through
+router.beforeEach(async (to) => {
+  const store = useUserStore()
+  if (to.name == "login" || store.uid != null) {
+    return true
+  }
+  if (!(await get_me())) {
+    return { name: 'login' }
+  }
+})
+
 export default router
\ No newline at end of file
@@ -2,9 +2,11 @@ import { defineStore } from 'pinia'
 import { ref } from 'vue'
 
 export const useUserStore = defineStore('user', () => {
-    const uid = ref(1)
+    // filled in once logged in
+    const uid = ref(null)
+    const username = ref(null)
 
-    return { uid }
+    return { uid, username }
 })
 
 export const useScrollStore = defineStore('scroll', () => {